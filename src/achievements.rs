@@ -0,0 +1,172 @@
+use anyhow::Context;
+use directories::ProjectDirs;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+/// What the achievements are checked against, the game so far.
+pub struct GameStats {
+    /// Most segments the first snake had at once.
+    pub longest: usize,
+    /// Game time played, not counting pauses.
+    pub survived_ms: u64,
+    pub score: u32,
+    pub bonuses_eaten: u32,
+    pub won: bool,
+    /// The snake can't wrap around any edge.
+    pub walls: bool,
+}
+
+pub struct Achievement {
+    /// Saved in the file, never change one.
+    pub id: &'static str,
+    pub name: &'static str,
+    pub unlocked: fn(&GameStats) -> bool,
+}
+
+pub const ALL: &[Achievement] = &[
+    Achievement {
+        id: "length_10",
+        name: "Reach length 10",
+        unlocked: |s| s.longest >= 10,
+    },
+    Achievement {
+        id: "length_50",
+        name: "Reach length 50",
+        unlocked: |s| s.longest >= 50,
+    },
+    Achievement {
+        id: "survive_5_min",
+        name: "Survive 5 minutes",
+        unlocked: |s| s.survived_ms >= 5 * 60 * 1000,
+    },
+    Achievement {
+        id: "score_100",
+        name: "Score 100 points",
+        unlocked: |s| s.score >= 100,
+    },
+    Achievement {
+        id: "bonus",
+        name: "Eat a bonus food",
+        unlocked: |s| s.bonuses_eaten > 0,
+    },
+    Achievement {
+        id: "win",
+        name: "Win a game",
+        unlocked: |s| s.won,
+    },
+    Achievement {
+        id: "win_walls",
+        name: "Win in walls mode",
+        unlocked: |s| s.won && s.walls,
+    },
+];
+
+/// Where unlocked achievements are kept, next to the stats.
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "desktop-snake").map(|dirs| dirs.data_dir().join("achievements.json"))
+}
+
+/// The achievements unlocked so far, ever.
+pub struct Unlocked {
+    path: PathBuf,
+    ids: BTreeSet<String>,
+}
+
+impl Unlocked {
+    /// Reads the ones saved in `path`. A missing file means none yet; a
+    /// corrupt one is reported and starts over.
+    pub fn load(path: PathBuf) -> Unlocked {
+        let ids = match std::fs::read_to_string(&path) {
+            Ok(text) => serde_json::from_str(&text).unwrap_or_else(|_| {
+                log::warn!(
+                    "starting over from corrupt achievements file {}",
+                    path.display()
+                );
+                BTreeSet::new()
+            }),
+            Err(_) => BTreeSet::new(),
+        };
+        Unlocked { path, ids }
+    }
+
+    /// Unlocks whatever `stats` earned that wasn't already, saving them if
+    /// there are any. Returns the ones just unlocked.
+    pub fn check(&mut self, stats: &GameStats) -> anyhow::Result<Vec<&'static Achievement>> {
+        let new: Vec<_> = ALL
+            .iter()
+            .filter(|a| !self.ids.contains(a.id) && (a.unlocked)(stats))
+            .collect();
+        if new.is_empty() {
+            return Ok(new);
+        }
+        for achievement in &new {
+            log::info!("Achievement unlocked: {}", achievement.name);
+            self.ids.insert(achievement.id.to_string());
+        }
+        log::info!("{} of {} achievements", self.ids.len(), ALL.len());
+        save(&self.path, &self.ids)
+            .with_context(|| format!("Failed to save achievements to {}", self.path.display()))?;
+        Ok(new)
+    }
+}
+
+fn save(path: &Path, ids: &BTreeSet<String>) -> anyhow::Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, serde_json::to_string(ids)?)?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn finished() -> GameStats {
+        GameStats {
+            longest: 12,
+            survived_ms: 6 * 60 * 1000,
+            score: 40,
+            bonuses_eaten: 0,
+            won: true,
+            walls: false,
+        }
+    }
+
+    fn ids(achievements: &[&Achievement]) -> Vec<&'static str> {
+        achievements.iter().map(|a| a.id).collect()
+    }
+
+    #[test]
+    fn a_finished_game_earns_what_it_reached() {
+        let stats = finished();
+        let earned: Vec<_> = ALL.iter().filter(|a| (a.unlocked)(&stats)).collect();
+        assert_eq!(ids(&earned), ["length_10", "survive_5_min", "win"]);
+
+        let stats = GameStats {
+            longest: 50,
+            score: 100,
+            bonuses_eaten: 1,
+            walls: true,
+            ..finished()
+        };
+        assert!(ALL.iter().all(|a| (a.unlocked)(&stats)));
+    }
+
+    #[test]
+    fn unlocked_only_once() {
+        let path = std::env::temp_dir().join("desktop-snake-achievements.json");
+        let _ = std::fs::remove_file(&path);
+        let mut unlocked = Unlocked::load(path.clone());
+        let new = unlocked.check(&finished()).unwrap();
+        assert_eq!(ids(&new), ["length_10", "survive_5_min", "win"]);
+        assert!(unlocked.check(&finished()).unwrap().is_empty());
+
+        // and still after loading them again
+        let mut unlocked = Unlocked::load(path.clone());
+        assert!(unlocked.check(&finished()).unwrap().is_empty());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
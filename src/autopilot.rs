@@ -0,0 +1,117 @@
+use crate::game::{GameState, SnakeDir};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+
+/// How well a computer-controlled snake finds its way.
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Skill {
+    /// Straight for the nearest food, only dodging what's right in front.
+    Greedy,
+    /// Shortest way around everything, see `next_dir`.
+    #[default]
+    Smart,
+}
+
+/// The first snake's move when the game plays itself: the autopilot at its
+/// best, or straight on when every move dies anyway.
+pub fn ai_next_dir(state: &GameState) -> SnakeDir {
+    state
+        .autopilot(0, Skill::Smart)
+        .unwrap_or_else(|| state.snakes[0].heading())
+}
+
+const DIRS: [SnakeDir; 4] = [
+    SnakeDir::Up,
+    SnakeDir::Down,
+    SnakeDir::Left,
+    SnakeDir::Right,
+];
+
+/// The first move of a shortest path from `head` to one of the `targets`
+/// over cells that aren't `blocked`. Without such a path, any move that
+/// doesn't die right away; `None` if there is no such move either.
+pub fn next_dir(
+    field: (usize, usize),
+    wrap: (bool, bool),
+    head: (usize, usize),
+    targets: &[(usize, usize)],
+    blocked: impl Fn((usize, usize)) -> bool,
+) -> Option<SnakeDir> {
+    // the move out of the head each reached cell came from
+    let mut first = HashMap::new();
+    let mut queue = VecDeque::new();
+
+    for dir in DIRS {
+        if let Some(cell) = neighbor(head, dir, field, wrap) {
+            if !blocked(cell) && !first.contains_key(&cell) {
+                first.insert(cell, dir);
+                queue.push_back(cell);
+            }
+        }
+    }
+    let fallback = queue.front().map(|cell| first[cell]);
+
+    while let Some(cell) = queue.pop_front() {
+        let dir = first[&cell];
+        if targets.contains(&cell) {
+            return Some(dir);
+        }
+        for next in DIRS.iter().filter_map(|&d| neighbor(cell, d, field, wrap)) {
+            if next != head && !blocked(next) && !first.contains_key(&next) {
+                first.insert(next, dir);
+                queue.push_back(next);
+            }
+        }
+    }
+
+    fallback
+}
+
+/// The move that doesn't die right away and gets closest to one of the
+/// `targets`, not minding what's in between. `None` if every move dies.
+pub fn greedy_dir(
+    field: (usize, usize),
+    wrap: (bool, bool),
+    head: (usize, usize),
+    targets: &[(usize, usize)],
+    blocked: impl Fn((usize, usize)) -> bool,
+) -> Option<SnakeDir> {
+    let distance = |(x, y): (usize, usize)| {
+        targets
+            .iter()
+            .map(|&(tx, ty)| x.abs_diff(tx) + y.abs_diff(ty))
+            .min()
+            .unwrap_or(0)
+    };
+    DIRS.iter()
+        .filter_map(|&dir| neighbor(head, dir, field, wrap).map(|cell| (dir, cell)))
+        .filter(|&(_, cell)| !blocked(cell))
+        .min_by_key(|&(_, cell)| distance(cell))
+        .map(|(dir, _)| dir)
+}
+
+/// The cell one step from `cell` towards `dir`, `None` past an edge that
+/// doesn't wrap. `wrap` is for the horizontal, then the vertical edges.
+fn neighbor(
+    (x, y): (usize, usize),
+    dir: SnakeDir,
+    (width, height): (usize, usize),
+    (wrap_x, wrap_y): (bool, bool),
+) -> Option<(usize, usize)> {
+    let (x, y) = (x as i64, y as i64);
+    let (nx, ny) = match dir {
+        SnakeDir::Up => (x, y - 1),
+        SnakeDir::Down => (x, y + 1),
+        SnakeDir::Left => (x - 1, y),
+        SnakeDir::Right => (x + 1, y),
+    };
+    let (w, h) = (width as i64, height as i64);
+    let nx = if wrap_x { nx.rem_euclid(w) } else { nx };
+    let ny = if wrap_y { ny.rem_euclid(h) } else { ny };
+    if (0..w).contains(&nx) && (0..h).contains(&ny) {
+        Some((nx as usize, ny as usize))
+    } else {
+        None
+    }
+}
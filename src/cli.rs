@@ -0,0 +1,80 @@
+use crate::Config;
+use clap::Parser;
+use std::path::PathBuf;
+
+/// Snake on your desktop icons. Flags override the config file.
+#[derive(Parser)]
+pub struct Args {
+    /// Config file to read, written with the defaults if missing.
+    #[arg(long, default_value = "config.toml")]
+    pub config: PathBuf,
+    /// Grid width, in icons.
+    #[arg(long)]
+    width: Option<u32>,
+    /// Grid height, in icons.
+    #[arg(long)]
+    height: Option<u32>,
+    /// Milliseconds between two moves.
+    #[arg(long)]
+    tick_ms: Option<u64>,
+    /// Seed for a reproducible game.
+    #[arg(long)]
+    seed: Option<u64>,
+    /// Folder to put the icons in instead of the desktop.
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// Lets the game play itself, ignoring the direction keys.
+    #[arg(long)]
+    autoplay: bool,
+    /// Plays a recorded game instead, with its own settings. Only the quit
+    /// and pause keys do anything.
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+    /// How many times faster than recorded to play the replay.
+    #[arg(long, default_value_t = 1.0)]
+    pub replay_speed: f64,
+    /// Logs more of what's going on, -vv for every key and tick. RUST_LOG
+    /// overrides it.
+    #[arg(short, long, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// Also prints the board in the terminal every tick, to check the game
+    /// without relying on the desktop redrawing.
+    #[arg(long)]
+    pub mirror_console: bool,
+}
+
+impl Args {
+    /// The least important messages shown, only warnings and errors by
+    /// default. What the player needs, like the seed, the record and how
+    /// the game ended, is printed whatever the level.
+    pub fn log_level(&self) -> log::LevelFilter {
+        match self.verbose {
+            0 => log::LevelFilter::Warn,
+            1 => log::LevelFilter::Info,
+            2 => log::LevelFilter::Debug,
+            _ => log::LevelFilter::Trace,
+        }
+    }
+
+    /// Puts the flags that were given on top of `config`.
+    pub fn apply(&self, config: &mut Config) {
+        if let Some(width) = self.width {
+            config.width = width;
+        }
+        if let Some(height) = self.height {
+            config.height = height;
+        }
+        if let Some(tick_ms) = self.tick_ms {
+            config.tick_ms = tick_ms;
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if self.autoplay {
+            config.autoplay = true;
+        }
+        if self.output_dir.is_some() {
+            config.output_dir = self.output_dir.clone();
+        }
+    }
+}
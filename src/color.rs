@@ -0,0 +1,245 @@
+use anyhow::Context;
+use bmp::Pixel;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// A color from the config, either `[r, g, b]` or a `"#RRGGBB"` string.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(untagged)]
+pub enum Color {
+    Rgb([u8; 3]),
+    Hex(String),
+}
+
+impl Color {
+    pub fn pixel(&self) -> anyhow::Result<Pixel> {
+        match self {
+            Color::Rgb([r, g, b]) => Ok(Pixel::new(*r, *g, *b)),
+            Color::Hex(text) => parse_hex_color(text),
+        }
+    }
+}
+
+/// Parses `#RRGGBB`, the `#` being optional.
+pub fn parse_hex_color(text: &str) -> anyhow::Result<Pixel> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    anyhow::ensure!(
+        hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+        "expected a color like #RRGGBB, found {:?}",
+        text
+    );
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+    Ok(Pixel::new(channel(0), channel(2), channel(4)))
+}
+
+/// `[colors]`, everything the game draws that isn't a sprite or a food
+/// kind of its own.
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct Palette {
+    pub background: Color,
+    pub food: Color,
+    /// The first player's, the second's then the computer's.
+    pub head: Color,
+    pub snake: Color,
+    pub player2_head: Color,
+    pub player2_snake: Color,
+    pub opponent_head: Color,
+    pub opponent_snake: Color,
+    /// A crashed snake.
+    pub dead: Color,
+    pub poison: Color,
+    pub bonus: Color,
+    pub ghost: Color,
+    /// The confusion food, and the snake that ate it.
+    pub confusion: Color,
+    pub slow: Color,
+    pub shrink: Color,
+    pub portal: Color,
+    /// Level walls, the border in wall mode and shrinking rings.
+    pub wall: Color,
+    /// The countdown and the messages over the board.
+    pub text: Color,
+    /// The snakes flashing on a win.
+    pub victory: Color,
+    /// The survival mode timer.
+    pub timer: Color,
+    /// The score, then the score once it beats the record.
+    pub digit: Color,
+    pub record: Color,
+    /// The combo multiplier at 2, fading to `combo_high` at the highest.
+    pub combo_low: Color,
+    pub combo_high: Color,
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            background: Color::Rgb([0, 0, 0]),
+            food: Color::Rgb([255, 0, 0]),
+            head: Color::Rgb([0, 255, 0]),
+            snake: Color::Rgb([255, 0, 0]),
+            player2_head: Color::Rgb([0, 255, 255]),
+            player2_snake: Color::Rgb([0, 96, 255]),
+            opponent_head: Color::Rgb([255, 160, 0]),
+            opponent_snake: Color::Rgb([160, 80, 0]),
+            dead: Color::Rgb([96, 96, 96]),
+            poison: Color::Rgb([0, 160, 0]),
+            bonus: Color::Rgb([255, 255, 0]),
+            ghost: Color::Rgb([180, 180, 255]),
+            confusion: Color::Rgb([200, 60, 220]),
+            slow: Color::Rgb([0, 200, 200]),
+            shrink: Color::Rgb([255, 255, 255]),
+            portal: Color::Rgb([160, 0, 255]),
+            wall: Color::Rgb([90, 60, 30]),
+            text: Color::Rgb([255, 255, 255]),
+            victory: Color::Rgb([0, 255, 0]),
+            timer: Color::Rgb([255, 255, 0]),
+            digit: Color::Rgb([255, 255, 255]),
+            record: Color::Rgb([255, 200, 0]),
+            combo_low: Color::Rgb([255, 255, 0]),
+            combo_high: Color::Rgb([255, 0, 0]),
+        }
+    }
+}
+
+/// The palette checked and ready to draw with.
+#[derive(Clone, Copy)]
+pub struct Pixels {
+    pub background: Pixel,
+    pub food: Pixel,
+    pub head: Pixel,
+    pub snake: Pixel,
+    pub player2_head: Pixel,
+    pub player2_snake: Pixel,
+    pub opponent_head: Pixel,
+    pub opponent_snake: Pixel,
+    pub dead: Pixel,
+    pub poison: Pixel,
+    pub bonus: Pixel,
+    pub ghost: Pixel,
+    pub confusion: Pixel,
+    pub slow: Pixel,
+    pub shrink: Pixel,
+    pub portal: Pixel,
+    pub wall: Pixel,
+    pub text: Pixel,
+    pub victory: Pixel,
+    pub timer: Pixel,
+    pub digit: Pixel,
+    pub record: Pixel,
+    pub combo_low: Pixel,
+    pub combo_high: Pixel,
+}
+
+// top-level keys from before `[colors]`, and where they went in it
+const MOVED: [(&str, &str); 17] = [
+    ("background", "background"),
+    ("food", "food"),
+    ("head_color", "head"),
+    ("body_color", "snake"),
+    ("snake", "snake"),
+    ("player2_head_color", "player2_head"),
+    ("player2_body_color", "player2_snake"),
+    ("opponent_head_color", "opponent_head"),
+    ("opponent_body_color", "opponent_snake"),
+    ("poison_color", "poison"),
+    ("bonus_color", "bonus"),
+    ("ghost_color", "ghost"),
+    ("confusion_color", "confusion"),
+    ("slow_color", "slow"),
+    ("shrink_color", "shrink"),
+    ("portal_color", "portal"),
+    ("wall_color", "wall"),
+];
+
+impl Palette {
+    /// Takes the colors an older config sets at the top level out of `old`,
+    /// the keys the config didn't know. They win over `[colors]`.
+    pub fn adopt(&mut self, old: &mut BTreeMap<String, Value>) -> anyhow::Result<()> {
+        let mut table = serde_json::to_value(&*self)?;
+        for (old_key, key) in MOVED {
+            if let Some(value) = old.remove(old_key) {
+                let color: Color = serde_json::from_value(value)
+                    .with_context(|| format!("Invalid {}", old_key))?;
+                color
+                    .pixel()
+                    .with_context(|| format!("Invalid {}", old_key))?;
+                table[key] = serde_json::to_value(color)?;
+            }
+        }
+        *self = serde_json::from_value(table)?;
+        Ok(())
+    }
+
+    /// Every color parsed, or an error naming the first invalid one.
+    pub fn pixels(&self) -> anyhow::Result<Pixels> {
+        let pixel = |color: &Color, key| {
+            color
+                .pixel()
+                .with_context(|| format!("Invalid colors.{}", key))
+        };
+        Ok(Pixels {
+            background: pixel(&self.background, "background")?,
+            food: pixel(&self.food, "food")?,
+            head: pixel(&self.head, "head")?,
+            snake: pixel(&self.snake, "snake")?,
+            player2_head: pixel(&self.player2_head, "player2_head")?,
+            player2_snake: pixel(&self.player2_snake, "player2_snake")?,
+            opponent_head: pixel(&self.opponent_head, "opponent_head")?,
+            opponent_snake: pixel(&self.opponent_snake, "opponent_snake")?,
+            dead: pixel(&self.dead, "dead")?,
+            poison: pixel(&self.poison, "poison")?,
+            bonus: pixel(&self.bonus, "bonus")?,
+            ghost: pixel(&self.ghost, "ghost")?,
+            confusion: pixel(&self.confusion, "confusion")?,
+            slow: pixel(&self.slow, "slow")?,
+            shrink: pixel(&self.shrink, "shrink")?,
+            portal: pixel(&self.portal, "portal")?,
+            wall: pixel(&self.wall, "wall")?,
+            text: pixel(&self.text, "text")?,
+            victory: pixel(&self.victory, "victory")?,
+            timer: pixel(&self.timer, "timer")?,
+            digit: pixel(&self.digit, "digit")?,
+            record: pixel(&self.record, "record")?,
+            combo_low: pixel(&self.combo_low, "combo_low")?,
+            combo_high: pixel(&self.combo_high, "combo_high")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_top_level_keys_move_into_the_palette() {
+        let mut old: BTreeMap<String, Value> = serde_json::from_str(
+            r##"{"body_color": "#0000FF", "background": [1, 2, 3], "width": 5}"##,
+        )
+        .unwrap();
+        let mut palette = Palette::default();
+        palette.adopt(&mut old).unwrap();
+        let pixels = palette.pixels().unwrap();
+        assert_eq!(pixels.snake, Pixel::new(0, 0, 255));
+        assert_eq!(pixels.background, Pixel::new(1, 2, 3));
+        // not a color, left alone
+        assert!(old.contains_key("width"));
+    }
+
+    #[test]
+    fn invalid_hex_names_the_key() {
+        let mut old: BTreeMap<String, Value> =
+            serde_json::from_str(r##"{"wall_color": "#12345G"}"##).unwrap();
+        let error = Palette::default().adopt(&mut old).unwrap_err();
+        assert_eq!(error.to_string(), "Invalid wall_color");
+
+        let palette = Palette {
+            text: Color::Hex("red".to_string()),
+            ..Palette::default()
+        };
+        let error = palette.pixels().err().unwrap();
+        assert_eq!(error.to_string(), "Invalid colors.text");
+    }
+}
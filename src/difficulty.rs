@@ -0,0 +1,155 @@
+use crate::{Config, EdgeBehavior};
+use serde::{Deserialize, Serialize};
+
+/// Bundled settings for people who'd rather not tune every knob.
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Difficulty {
+    Easy,
+    Normal,
+    Hard,
+    /// Only the config file's own settings.
+    #[default]
+    Custom,
+}
+
+/// The settings a preset covers, as far as the config file sets them.
+/// Read alongside `Config` to tell a setting left to its default from one
+/// set to the default value.
+#[derive(Deserialize, Default)]
+pub struct Explicit {
+    #[serde(alias = "speed_max_ms")]
+    tick_ms: Option<u64>,
+    speed_step_ms: Option<u64>,
+    #[serde(alias = "grow_by")]
+    growth_per_food: Option<usize>,
+    edge_behavior: Option<EdgeBehavior>,
+    poison_chance: Option<f64>,
+    obstacles: Option<usize>,
+}
+
+struct Preset {
+    tick_ms: u64,
+    speed_step_ms: u64,
+    growth_per_food: usize,
+    edge_behavior: EdgeBehavior,
+    poison_chance: f64,
+    obstacles: usize,
+}
+
+impl Difficulty {
+    fn preset(self) -> Option<Preset> {
+        match self {
+            Difficulty::Easy => Some(Preset {
+                tick_ms: 1500,
+                speed_step_ms: 0,
+                growth_per_food: 1,
+                edge_behavior: EdgeBehavior::Wrap,
+                poison_chance: 0.0,
+                obstacles: 0,
+            }),
+            // the defaults
+            Difficulty::Normal => Some(Preset {
+                tick_ms: 1200,
+                speed_step_ms: 0,
+                growth_per_food: 1,
+                edge_behavior: EdgeBehavior::Wrap,
+                poison_chance: 0.0,
+                obstacles: 0,
+            }),
+            Difficulty::Hard => Some(Preset {
+                tick_ms: 600,
+                speed_step_ms: 20,
+                growth_per_food: 2,
+                edge_behavior: EdgeBehavior::Wall,
+                poison_chance: 0.2,
+                obstacles: 8,
+            }),
+            Difficulty::Custom => None,
+        }
+    }
+}
+
+/// Fills the settings of `config` that the file left out, listed in
+/// `explicit`, from its difficulty preset. Anything set in the file wins.
+pub fn apply(config: &mut Config, explicit: &Explicit) {
+    let Some(preset) = config.difficulty.preset() else {
+        return;
+    };
+    config.tick_ms = explicit.tick_ms.unwrap_or(preset.tick_ms);
+    config.speed_step_ms = explicit.speed_step_ms.unwrap_or(preset.speed_step_ms);
+    config.growth_per_food = explicit.growth_per_food.unwrap_or(preset.growth_per_food);
+    config.edge_behavior = explicit.edge_behavior.unwrap_or(preset.edge_behavior);
+    config.poison_chance = explicit.poison_chance.unwrap_or(preset.poison_chance);
+    config.obstacles = explicit.obstacles.unwrap_or(preset.obstacles);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Args;
+    use clap::Parser;
+
+    fn hard() -> Config {
+        Config {
+            difficulty: Difficulty::Hard,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn preset_fills_what_the_file_leaves_out() {
+        let mut config = hard();
+        apply(&mut config, &Explicit::default());
+        assert_eq!(config.tick_ms, 600);
+        assert_eq!(config.growth_per_food, 2);
+        assert!(config.edge_behavior == EdgeBehavior::Wall);
+        assert_eq!(config.obstacles, 8);
+    }
+
+    #[test]
+    fn file_beats_preset() {
+        let mut config = hard();
+        let explicit = Explicit {
+            tick_ms: Some(900),
+            obstacles: Some(0),
+            ..Explicit::default()
+        };
+        // what the file set made it into the config already
+        config.tick_ms = 900;
+        config.obstacles = 0;
+        apply(&mut config, &explicit);
+        assert_eq!(config.tick_ms, 900);
+        assert_eq!(config.obstacles, 0);
+        // the rest still comes from the preset
+        assert_eq!(config.speed_step_ms, 20);
+        assert_eq!(config.poison_chance, 0.2);
+    }
+
+    #[test]
+    fn flags_beat_file_and_preset() {
+        let mut config = hard();
+        config.width = 10;
+        let explicit = Explicit {
+            tick_ms: Some(900),
+            ..Explicit::default()
+        };
+        config.tick_ms = 900;
+        apply(&mut config, &explicit);
+        Args::parse_from(["desktop-snake", "--tick-ms", "300", "--width", "12"]).apply(&mut config);
+        assert_eq!(config.tick_ms, 300);
+        assert_eq!(config.width, 12);
+        assert_eq!(config.growth_per_food, 2);
+    }
+
+    #[test]
+    fn custom_keeps_the_file() {
+        let mut config = Config {
+            tick_ms: 700,
+            ..Config::default()
+        };
+        apply(&mut config, &Explicit::default());
+        assert_eq!(config.tick_ms, 700);
+        assert_eq!(config.obstacles, 0);
+    }
+}
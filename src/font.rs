@@ -0,0 +1,93 @@
+pub const GLYPH_WIDTH: usize = 3;
+pub const GLYPH_HEIGHT: usize = 5;
+
+// one row per entry, bit 2 is the leftmost column
+fn glyph(c: char) -> [u8; GLYPH_HEIGHT] {
+    match c.to_ascii_uppercase() {
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b110, 0b001, 0b010, 0b100, 0b111],
+        '3' => [0b110, 0b001, 0b010, 0b001, 0b110],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b110, 0b001, 0b110],
+        '6' => [0b011, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b010, 0b010, 0b010],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b110],
+        '!' => [0b010, 0b010, 0b010, 0b000, 0b010],
+        // space and anything we don't know about
+        _ => [0; GLYPH_HEIGHT],
+    }
+}
+
+/// Width in cells of `line`, with one blank column between glyphs.
+pub fn text_width(line: &str) -> usize {
+    (line.chars().count() * (GLYPH_WIDTH + 1)).saturating_sub(1)
+}
+
+/// Lit cells of `line` drawn with its top-left corner at `(x, y)`.
+pub fn text_cells(line: &str, x: usize, y: usize) -> Vec<(usize, usize)> {
+    let mut cells = Vec::new();
+    for (i, c) in line.chars().enumerate() {
+        let left = x + i * (GLYPH_WIDTH + 1);
+        for (dy, row) in glyph(c).iter().enumerate() {
+            for dx in 0..GLYPH_WIDTH {
+                if row & (1 << (GLYPH_WIDTH - 1 - dx)) != 0 {
+                    cells.push((left + dx, y + dy));
+                }
+            }
+        }
+    }
+    cells
+}
+
+/// Lit cells of the first variant that fits in a `width`x`height` grid,
+/// centered. A variant is a list of lines stacked with one blank row between
+/// them, so callers can offer a one-line version, a wrapped one, then
+/// abbreviations.
+pub fn centered(variants: &[&[&str]], width: usize, height: usize) -> Option<Vec<(usize, usize)>> {
+    variants.iter().find_map(|lines| {
+        let text_w = lines.iter().map(|l| text_width(l)).max().unwrap_or(0);
+        let text_h = (lines.len() * (GLYPH_HEIGHT + 1)).saturating_sub(1);
+        if text_w > width || text_h > height {
+            return None;
+        }
+
+        let top = (height - text_h) / 2;
+        let cells = lines
+            .iter()
+            .enumerate()
+            .flat_map(|(i, line)| {
+                let left = (width - text_width(line)) / 2;
+                text_cells(line, left, top + i * (GLYPH_HEIGHT + 1))
+            })
+            .collect();
+        Some(cells)
+    })
+}
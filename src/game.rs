@@ -0,0 +1,1694 @@
+use crate::autopilot::{self, Skill};
+use crate::level::Level;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+// segments gained by eating a bonus food
+const BONUS_GROWTH: usize = 3;
+
+// random picks before spawn_food gives up and lists the free cells
+const SPAWN_ATTEMPTS: usize = 16;
+
+// last ticks of a ghost effect, during which the snake flashes
+const GHOST_WARNING_TICKS: u32 = 2;
+
+// last ticks of a food before it moves, during which it blinks
+const FOOD_WARNING_TICKS: u32 = 4;
+
+/// Highest score multiplier a chain of food can reach.
+pub const MAX_COMBO: u32 = 5;
+
+#[derive(Serialize, Deserialize, Copy, Clone, PartialEq, Eq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum SnakeDir {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl SnakeDir {
+    pub fn opposite(self) -> SnakeDir {
+        match self {
+            SnakeDir::Up => SnakeDir::Down,
+            SnakeDir::Down => SnakeDir::Up,
+            SnakeDir::Left => SnakeDir::Right,
+            SnakeDir::Right => SnakeDir::Left,
+        }
+    }
+
+    /// One step in this direction, as (dx, dy).
+    pub fn delta(self) -> (i32, i32) {
+        match self {
+            SnakeDir::Up => (0, -1),
+            SnakeDir::Down => (0, 1),
+            SnakeDir::Left => (-1, 0),
+            SnakeDir::Right => (1, 0),
+        }
+    }
+}
+
+/// Where the snake is heading, plus the turns pressed since the last tick.
+/// Shared with the keyboard thread.
+pub struct Steering {
+    heading: SnakeDir,
+    queue: VecDeque<SnakeDir>,
+    // turns remembered ahead of the ticks that apply them
+    capacity: usize,
+    /// Key presses steer the other way.
+    pub confused: bool,
+}
+
+impl Steering {
+    pub fn new(heading: SnakeDir, capacity: usize) -> Steering {
+        Steering {
+            heading,
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            confused: false,
+        }
+    }
+
+    /// Heads towards `heading` and forgets the queued turns.
+    pub fn reset(&mut self, heading: SnakeDir) {
+        self.heading = heading;
+        self.queue.clear();
+        self.confused = false;
+    }
+
+    pub fn heading(&self) -> SnakeDir {
+        self.heading
+    }
+
+    /// Queues the turn a player asked for, which is the opposite one while
+    /// confused. Turns already queued keep their direction.
+    pub fn press(&mut self, dir: SnakeDir) -> bool {
+        self.push(if self.confused { dir.opposite() } else { dir })
+    }
+
+    /// Queues a turn for an upcoming tick. It's checked against the last
+    /// queued direction, not the current one, so two quick presses can't add
+    /// up to a U-turn. Returns whether the turn was kept.
+    pub fn push(&mut self, dir: SnakeDir) -> bool {
+        let last = self.queue.back().copied().unwrap_or(self.heading);
+        if dir == last || dir == last.opposite() || self.queue.len() >= self.capacity {
+            return false;
+        }
+        self.queue.push_back(dir);
+        true
+    }
+
+    /// Applies the next queued turn, if any, and returns the new heading.
+    /// The turn is checked again against the heading actually applied, so
+    /// nothing that slipped into the queue can reverse the snake into
+    /// itself.
+    pub fn next(&mut self) -> SnakeDir {
+        while let Some(dir) = self.queue.pop_front() {
+            if dir != self.heading.opposite() {
+                self.heading = dir;
+                break;
+            }
+        }
+        self.heading
+    }
+}
+
+/// What the game is about.
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    /// Eat as much as possible before crashing.
+    #[default]
+    Classic,
+    /// Stay alive until the timer runs out while obstacles pile up.
+    Survival,
+    /// No food, the snakes leave a trail that never goes away. Every tick
+    /// survived is a point.
+    Tron,
+}
+
+/// What running into something does to a snake.
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Collision {
+    /// It crashes, which costs a life.
+    #[default]
+    Crash,
+    /// It stays put until it turns away.
+    Stop,
+    /// It goes through, coming back in on the other side of an edge.
+    Pass,
+}
+
+/// Locks a `Steering` shared with the input threads.
+///
+/// If one of them panicked while holding it, the lock is poisoned but the
+/// steering itself is fine: none of its methods can leave it half updated.
+/// So instead of taking the game down too, this keeps going with the last
+/// known heading and whatever turns were queued.
+pub fn lock(steering: &Mutex<Steering>) -> MutexGuard<'_, Steering> {
+    steering.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// What a cell of the playfield should be drawn as.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Cell {
+    Empty,
+    /// Head of the given player's snake.
+    Head(usize),
+    /// Rest of the given player's snake.
+    Snake(usize),
+    /// Rest of the given player's snake while it can cross itself.
+    Ghost(usize),
+    /// Power-up making the snake a ghost.
+    GhostFood,
+    /// Rest of the given player's snake while its controls are reversed.
+    Confused(usize),
+    /// Food reversing the controls of whoever eats it.
+    ConfusionFood,
+    /// Power-up slowing the game down.
+    SlowFood,
+    /// Power-up cutting the tail of whoever eats it.
+    ShrinkFood,
+    /// One end of a portal.
+    Portal,
+    Dead,
+    /// Food of the given kind.
+    Food(usize),
+    /// Food about to move elsewhere, every other tick.
+    FadingFood(usize),
+    Bonus,
+    Poison,
+    Wall,
+}
+
+/// What eating one kind of food does, and how often it spawns.
+pub struct FoodKind {
+    pub growth: usize,
+    /// Points before the combo multiplier.
+    pub score: u32,
+    /// Chance of spawning relative to the other kinds.
+    pub weight: u32,
+}
+
+/// A cell of the playfield that needs redrawing.
+pub type CellUpdate = (usize, usize, Cell);
+
+/// The knobs from the config that affect the simulation.
+pub struct Rules {
+    pub food_count: usize,
+    /// At least one. Level food is the first kind.
+    pub foods: Vec<FoodKind>,
+    /// Leaving the playfield horizontally, then vertically, wraps around
+    /// instead of killing the snake.
+    pub wrap: (bool, bool),
+    /// Level walls stop the snake instead of killing it.
+    pub solid_walls: bool,
+    /// What hitting anything does, for practice runs that can't be lost.
+    /// Level walls that are solid stop the snake at least.
+    pub collision: Collision,
+    pub bonus_interval: u32,
+    pub bonus_ticks: u32,
+    pub bonus_score: u32,
+    pub poison_chance: f64,
+    pub poison_penalty: usize,
+    /// Chance that eating food spawns a ghost power-up.
+    pub ghost_chance: f64,
+    /// Ticks a ghost power-up lets the snake pass through itself.
+    pub ghost_ticks: u32,
+    /// Chance that eating food spawns a confusion food.
+    pub confusion_chance: f64,
+    /// Ticks the controls stay reversed after eating one.
+    pub confusion_ticks: u32,
+    /// Chance that eating food spawns a slow motion power-up.
+    pub slow_chance: f64,
+    /// Ticks the game stays slowed after eating one.
+    pub slow_ticks: u32,
+    /// Chance that eating food spawns a shrink power-up.
+    pub shrink_chance: f64,
+    /// Tail segments a shrink power-up cuts, the head always stays.
+    pub shrink_segments: usize,
+    pub mode: Mode,
+    /// Time to survive in survival mode.
+    pub survival_ms: u64,
+    /// Time between two obstacles in survival mode, 0 for none.
+    pub obstacle_ms: u64,
+    /// Ticks between two obstacles in any mode, 0 for none.
+    pub obstacle_interval: u32,
+    /// Obstacles added during a game stop here, 0 for no limit.
+    pub max_obstacles: usize,
+    /// Crashes each snake can take, the last one ends the game.
+    pub lives: u32,
+    /// Which way the first snake starts, the second one goes the other way.
+    pub start_dir: SnakeDir,
+    pub start_length: usize,
+    /// Ticks between two rings of the arena turning into walls, 0 never.
+    pub shrink_interval: u32,
+    /// The arena stops shrinking before going under this many cells.
+    pub shrink_min_area: usize,
+    /// Ticks food stays uneaten before moving elsewhere, 0 forever.
+    pub food_ttl: u32,
+    /// Eating food within this many ticks of the previous one raises the
+    /// score multiplier, 0 never.
+    pub combo_ticks: u32,
+    /// The last snake is the computer's.
+    pub opponent: bool,
+}
+
+/// One player's snake.
+pub struct Snake {
+    bits: Vec<(usize, usize)>,
+    cells: HashSet<(usize, usize)>,
+    // shared with the input threads
+    steering: Arc<Mutex<Steering>>,
+    pending_growth: usize,
+    // ticks left of the ghost effect
+    ghost_ticks: u32,
+    // ticks left with reversed controls
+    confused_ticks: u32,
+    pub score: u32,
+    /// Points the next food is worth if it keeps the chain going.
+    pub combo: u32,
+    // tick of the last food eaten
+    last_food: Option<u64>,
+    /// Crashes left, counting the one that ends the game.
+    pub lives: u32,
+    pub alive: bool,
+    /// Played by the computer, its score doesn't count as a record.
+    pub computer: bool,
+}
+
+impl Snake {
+    pub fn head(&self) -> (usize, usize) {
+        *self.bits.last().unwrap()
+    }
+
+    pub fn heading(&self) -> SnakeDir {
+        lock(&self.steering).heading()
+    }
+
+    /// Segments, head included.
+    pub fn length(&self) -> usize {
+        self.bits.len()
+    }
+
+    /// Segments behind the head, nearest first.
+    pub fn body(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        self.bits.iter().rev().skip(1).copied()
+    }
+
+    /// Drawn as a ghost, blinking once the effect is about to end.
+    fn looks_ghost(&self) -> bool {
+        self.ghost_ticks > GHOST_WARNING_TICKS || self.ghost_ticks % 2 == 1
+    }
+
+    /// What the segment at `cell` of player `i` looks like.
+    fn look(&self, i: usize, cell: (usize, usize)) -> Cell {
+        if !self.alive {
+            Cell::Dead
+        } else if self.head() == cell {
+            Cell::Head(i)
+        } else {
+            self.body_look(i)
+        }
+    }
+
+    /// What the segments behind the head of player `i` look like.
+    fn body_look(&self, i: usize) -> Cell {
+        if self.looks_ghost() {
+            Cell::Ghost(i)
+        } else if self.confused_ticks > 0 {
+            Cell::Confused(i)
+        } else {
+            Cell::Snake(i)
+        }
+    }
+
+    /// Drops a segment that was at `cell`. Returns whether the cell is free
+    /// now, a ghost can have another segment on it.
+    fn forget(&mut self, cell: (usize, usize)) -> bool {
+        let free = !self.bits.contains(&cell);
+        if free {
+            self.cells.remove(&cell);
+        }
+        free
+    }
+
+    /// Every segment with its look.
+    fn drawn(&self, i: usize) -> impl Iterator<Item = CellUpdate> + '_ {
+        self.bits
+            .iter()
+            .map(move |&(x, y)| (x, y, self.look(i, (x, y))))
+    }
+}
+
+/// Where a snake is headed this tick, worked out before anything moves so
+/// the snakes can't get an edge from the order they're processed in.
+struct Move {
+    new: (usize, usize),
+    // the tail moves out of the way, unless the snake is growing
+    vacated: Option<(usize, usize)>,
+    growth: usize,
+    blocked: bool,
+    dies: bool,
+}
+
+pub struct GameState {
+    rules: Rules,
+    // playfield (width, height)
+    field: (usize, usize),
+    level: Level,
+    pub snakes: Vec<Snake>,
+    food: Vec<(usize, usize)>,
+    // the last top-up found no free cell for food
+    out_of_room: bool,
+    // ticks each food has left with a `food_ttl`, from its first tick
+    food_ticks: HashMap<(usize, usize), u32>,
+    // kind of each spawned food, level food is the first
+    food_kinds: HashMap<(usize, usize), usize>,
+    // bonus food position and the ticks it has left
+    bonus: Option<((usize, usize), u32)>,
+    poison: Vec<(usize, usize)>,
+    ghost: Option<(usize, usize)>,
+    confusion: Option<(usize, usize)>,
+    slow: Option<(usize, usize)>,
+    shrink: Option<(usize, usize)>,
+    // first tick back at full speed after a slow motion power-up
+    slow_until_tick: u64,
+    // walls added to the level during the game
+    obstacles: Vec<(usize, usize)>,
+    // how many of those were random obstacles
+    dropped: usize,
+    since_dropped: u32,
+    // the part of the field left by the shrinking walls, from the first
+    // cell to one past the last
+    arena: ((usize, usize), (usize, usize)),
+    since_shrink: u32,
+    pub foods_eaten: u32,
+    pub bonuses_eaten: u32,
+    // ticks played since the program started
+    ticks: u64,
+    /// Survival time left.
+    pub time_left_ms: u64,
+    since_obstacle_ms: u64,
+    pub game_over: bool,
+    /// The game ended well, not with a crash.
+    pub won: bool,
+    rng: StdRng,
+}
+
+impl GameState {
+    /// One snake per `steering`. `rng` drives every random placement, so a
+    /// seeded one replays the same game.
+    pub fn new(
+        rules: Rules,
+        field: (usize, usize),
+        level: Level,
+        steering: Vec<Arc<Mutex<Steering>>>,
+        rng: StdRng,
+    ) -> GameState {
+        let computer = rules.opponent.then(|| steering.len() - 1);
+        let snakes = steering
+            .into_iter()
+            .enumerate()
+            .map(|(i, steering)| Snake {
+                bits: Vec::new(),
+                cells: HashSet::new(),
+                steering,
+                pending_growth: 0,
+                ghost_ticks: 0,
+                confused_ticks: 0,
+                score: 0,
+                combo: 1,
+                last_food: None,
+                lives: 0,
+                alive: true,
+                computer: computer == Some(i),
+            })
+            .collect();
+        let mut state = GameState {
+            rules,
+            field,
+            level,
+            snakes,
+            food: Vec::new(),
+            out_of_room: false,
+            food_ticks: HashMap::new(),
+            food_kinds: HashMap::new(),
+            bonus: None,
+            poison: Vec::new(),
+            ghost: None,
+            confusion: None,
+            slow: None,
+            slow_until_tick: 0,
+            shrink: None,
+            obstacles: Vec::new(),
+            dropped: 0,
+            since_dropped: 0,
+            arena: ((0, 0), field),
+            since_shrink: 0,
+            foods_eaten: 0,
+            bonuses_eaten: 0,
+            ticks: 0,
+            time_left_ms: 0,
+            since_obstacle_ms: 0,
+            game_over: false,
+            won: false,
+            rng,
+        };
+        state.start();
+        state
+    }
+
+    fn start(&mut self) {
+        self.clear_obstacles();
+        self.foods_eaten = 0;
+        self.bonuses_eaten = 0;
+        self.time_left_ms = self.rules.survival_ms;
+        self.since_obstacle_ms = 0;
+        self.game_over = false;
+        self.won = false;
+        for snake in &mut self.snakes {
+            snake.score = 0;
+            snake.lives = self.rules.lives;
+            snake.alive = true;
+        }
+        self.place();
+    }
+
+    /// Puts the level's food on the board and the snakes at their starts.
+    /// Food the starting bodies cover is spawned elsewhere instead.
+    fn place(&mut self) {
+        self.food = if self.rules.mode == Mode::Tron {
+            Vec::new()
+        } else {
+            self.level.food.clone()
+        };
+        self.food_ticks.clear();
+        self.food_kinds.clear();
+        self.bonus = None;
+        self.poison.clear();
+        self.ghost = None;
+        self.confusion = None;
+        self.slow = None;
+        self.slow_until_tick = 0;
+        self.shrink = None;
+
+        for snake in &mut self.snakes {
+            snake.bits.clear();
+            snake.cells.clear();
+        }
+        for i in 0..self.snakes.len() {
+            self.put_at_start(i);
+        }
+        // the empty level's food is off a board this small
+        let (snakes, (width, height)) = (&self.snakes, self.field);
+        self.food.retain(|&(x, y)| {
+            x < width && y < height && !snakes.iter().any(|s| s.cells.contains(&(x, y)))
+        });
+        self.top_up_food();
+    }
+
+    /// Snake `i` back to its starting cell and length. The body trails
+    /// behind the head, cut short by whatever is in the way.
+    fn put_at_start(&mut self, i: usize) {
+        let (start, dir) = self.start_of(i);
+        let taken = |cell| {
+            self.level.walls.contains(&cell) || self.snakes.iter().any(|s| s.cells.contains(&cell))
+        };
+        let mut bits: Vec<_> = body_from(start, dir, self.rules.start_length, self.field)
+            .into_iter()
+            .take_while(|&cell| !taken(cell))
+            .collect();
+        bits.reverse();
+
+        let snake = &mut self.snakes[i];
+        snake.bits = bits;
+        snake.cells = snake.bits.iter().copied().collect();
+        lock(&snake.steering).reset(dir);
+        snake.pending_growth = 0;
+        snake.ghost_ticks = 0;
+        snake.confused_ticks = 0;
+        snake.combo = 1;
+        snake.last_food = None;
+    }
+
+    /// A slow motion power-up is in effect, for the wait after this tick.
+    pub fn slowed(&self) -> bool {
+        self.ticks < self.slow_until_tick
+    }
+
+    /// Some snake crashed, but it has lives left.
+    pub fn crashed(&self) -> bool {
+        !self.game_over && self.snakes.iter().any(|s| !s.alive)
+    }
+
+    /// Brings the crashed snakes back at their starts, keeping their
+    /// scores and everything else on the board. Whatever item was on a
+    /// start cell moves elsewhere. Returns the cells to redraw.
+    pub fn respawn(&mut self) -> Vec<CellUpdate> {
+        self.redrawn(|state| {
+            for i in 0..state.snakes.len() {
+                if state.snakes[i].alive {
+                    continue;
+                }
+                let snake = &mut state.snakes[i];
+                snake.bits.clear();
+                snake.cells.clear();
+                state.put_at_start(i);
+                state.snakes[i].alive = true;
+
+                let body = &state.snakes[i].cells;
+                state.food.retain(|f| !body.contains(f));
+                state.poison.retain(|p| !body.contains(p));
+                if state.bonus.is_some_and(|(c, _)| body.contains(&c)) {
+                    state.bonus = None;
+                }
+                if state.ghost.is_some_and(|c| body.contains(&c)) {
+                    state.ghost = None;
+                }
+                if state.confusion.is_some_and(|c| body.contains(&c)) {
+                    state.confusion = None;
+                }
+                if state.slow.is_some_and(|c| body.contains(&c)) {
+                    state.slow = None;
+                }
+                if state.shrink.is_some_and(|c| body.contains(&c)) {
+                    state.shrink = None;
+                }
+            }
+            state.top_up_food();
+        })
+    }
+
+    /// Where player `i` starts and which way it heads. The second player
+    /// mirrors the first across the middle of the field. Either goes
+    /// somewhere random if its spot is taken by the other snake.
+    fn start_of(&mut self, i: usize) -> ((usize, usize), SnakeDir) {
+        let (x, y) = self.level.start;
+        let (width, height) = self.field;
+        let (wanted, dir) = if i == 0 {
+            ((x, y), self.rules.start_dir)
+        } else {
+            (
+                (width - 1 - x, height - 1 - y),
+                self.rules.start_dir.opposite(),
+            )
+        };
+
+        let taken = |cell| {
+            self.level.walls.contains(&cell)
+                || self
+                    .snakes
+                    .iter()
+                    .enumerate()
+                    .any(|(j, s)| j != i && s.cells.contains(&cell))
+        };
+        let start = if taken(wanted) {
+            spawn_food(taken, self.field, &mut self.rng).unwrap_or(wanted)
+        } else {
+            wanted
+        };
+        (start, dir)
+    }
+
+    /// The best score among the human players.
+    pub fn score(&self) -> u32 {
+        self.snakes
+            .iter()
+            .filter(|s| !s.computer)
+            .map(|s| s.score)
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Back to the starting snakes with freshly spawned food. Returns only
+    /// the cells that look different from the board before.
+    pub fn reset(&mut self) -> Vec<CellUpdate> {
+        self.redrawn(GameState::start)
+    }
+
+    /// Swaps in `level` and puts the snakes back at its start, keeping
+    /// their scores. Returns the cells that look different, like `reset`.
+    pub fn next_level(&mut self, level: Level) -> Vec<CellUpdate> {
+        self.redrawn(|state| {
+            state.clear_obstacles();
+            state.level = level;
+            state.place();
+        })
+    }
+
+    /// Takes out the walls added during the game, which gives the arena its
+    /// full size back.
+    fn clear_obstacles(&mut self) {
+        for cell in self.obstacles.drain(..) {
+            self.level.walls.remove(&cell);
+        }
+        self.dropped = 0;
+        self.since_dropped = 0;
+        self.arena = ((0, 0), self.field);
+        self.since_shrink = 0;
+    }
+
+    /// Applies `change` and lists the cells it changed the look of.
+    fn redrawn(&mut self, change: impl FnOnce(&mut GameState)) -> Vec<CellUpdate> {
+        let before: HashMap<_, _> = self
+            .cells()
+            .into_iter()
+            .map(|(x, y, c)| ((x, y), c))
+            .collect();
+        change(self);
+
+        let mut updates: Vec<_> = self
+            .cells()
+            .into_iter()
+            .filter(|&(x, y, c)| before.get(&(x, y)) != Some(&c))
+            .collect();
+        updates.extend(
+            before
+                .keys()
+                .filter(|&&cell| self.cell(cell) == Cell::Empty)
+                .map(|&(x, y)| (x, y, Cell::Empty)),
+        );
+        updates
+    }
+
+    /// What `cell` should be drawn as right now.
+    pub fn cell(&self, cell: (usize, usize)) -> Cell {
+        if self.level.walls.contains(&cell) {
+            return Cell::Wall;
+        }
+        if let Some((i, snake)) = self
+            .snakes
+            .iter()
+            .enumerate()
+            .find(|(_, s)| s.cells.contains(&cell))
+        {
+            return snake.look(i, cell);
+        }
+        // a snake coming out of a portal sits on its exit for a tick
+        if self.level.portals.contains_key(&cell) {
+            return Cell::Portal;
+        }
+
+        if self.food.contains(&cell) {
+            self.food_look(cell)
+        } else if self.bonus.map(|(c, _)| c) == Some(cell) {
+            Cell::Bonus
+        } else if self.poison.contains(&cell) {
+            Cell::Poison
+        } else if self.ghost == Some(cell) {
+            Cell::GhostFood
+        } else if self.confusion == Some(cell) {
+            Cell::ConfusionFood
+        } else if self.slow == Some(cell) {
+            Cell::SlowFood
+        } else if self.shrink == Some(cell) {
+            Cell::ShrinkFood
+        } else {
+            Cell::Empty
+        }
+    }
+
+    /// How the food at `cell` looks, blinking when it's about to move.
+    fn food_look(&self, cell: (usize, usize)) -> Cell {
+        let kind = self.food_kind(cell);
+        match self.food_ticks.get(&cell) {
+            Some(&left) if left <= FOOD_WARNING_TICKS && left % 2 == 0 => Cell::FadingFood(kind),
+            _ => Cell::Food(kind),
+        }
+    }
+
+    fn food_kind(&self, cell: (usize, usize)) -> usize {
+        self.food_kinds.get(&cell).copied().unwrap_or(0)
+    }
+
+    /// Every non-empty cell of the board, to draw it from scratch.
+    pub fn cells(&self) -> Vec<CellUpdate> {
+        let mut cells: Vec<_> = self
+            .level
+            .walls
+            .iter()
+            .map(|&(x, y)| (x, y, Cell::Wall))
+            .collect();
+        cells.extend(
+            self.level
+                .portals
+                .keys()
+                .map(|&(x, y)| (x, y, Cell::Portal)),
+        );
+        for (i, snake) in self.snakes.iter().enumerate() {
+            cells.extend(snake.drawn(i));
+        }
+        cells.extend(
+            self.food
+                .iter()
+                .map(|&(x, y)| (x, y, self.food_look((x, y)))),
+        );
+        cells.extend(self.bonus.map(|((x, y), _)| (x, y, Cell::Bonus)));
+        cells.extend(self.poison.iter().map(|&(x, y)| (x, y, Cell::Poison)));
+        cells.extend(self.ghost.map(|(x, y)| (x, y, Cell::GhostFood)));
+        cells.extend(self.confusion.map(|(x, y)| (x, y, Cell::ConfusionFood)));
+        cells.extend(self.slow.map(|(x, y)| (x, y, Cell::SlowFood)));
+        cells.extend(self.shrink.map(|(x, y)| (x, y, Cell::ShrinkFood)));
+        cells
+    }
+
+    /// Picks a cell nothing sits on, or `None` if the board is full.
+    fn spawn_food(&mut self) -> Option<(usize, usize)> {
+        self.spawn_where(|_| true)
+    }
+
+    /// Like `spawn_food`, among the cells that are `allowed`.
+    fn spawn_where(&mut self, allowed: impl Fn((usize, usize)) -> bool) -> Option<(usize, usize)> {
+        let GameState {
+            field,
+            level,
+            snakes,
+            food,
+            bonus,
+            poison,
+            ghost,
+            confusion,
+            slow,
+            shrink,
+            rng,
+            ..
+        } = self;
+        let taken = |cell| {
+            !allowed(cell)
+                || snakes.iter().any(|s| s.cells.contains(&cell))
+                || level.walls.contains(&cell)
+                || level.portals.contains_key(&cell)
+                || food.contains(&cell)
+                || bonus.map(|(c, _)| c) == Some(cell)
+                || poison.contains(&cell)
+                || *ghost == Some(cell)
+                || *confusion == Some(cell)
+                || *slow == Some(cell)
+                || *shrink == Some(cell)
+        };
+        spawn_food(taken, *field, rng)
+    }
+
+    /// Spawns food until there are as many items as the rules want,
+    /// returning the newly placed ones. There may be fewer once the board
+    /// fills up, which `out_of_room` keeps track of.
+    fn top_up_food(&mut self) -> Vec<(usize, usize)> {
+        let mut spawned = Vec::new();
+        self.out_of_room = false;
+        if self.rules.mode == Mode::Tron {
+            return spawned;
+        }
+        while self.food.len() < self.rules.food_count {
+            match self.spawn_food() {
+                Some(cell) => {
+                    // no draw with a single kind, so seeds play out like
+                    // before there were kinds
+                    let kind = if self.rules.foods.len() > 1 {
+                        let weights = self.rules.foods.iter().map(|f| f.weight);
+                        WeightedIndex::new(weights).map_or(0, |w| w.sample(&mut self.rng))
+                    } else {
+                        0
+                    };
+                    self.food_kinds.insert(cell, kind);
+                    self.food.push(cell);
+                    spawned.push(cell);
+                }
+                None => {
+                    self.out_of_room = true;
+                    break;
+                }
+            }
+        }
+        spawned
+    }
+
+    /// The move the autopilot would make for player `i`: towards the
+    /// nearest food, around the snakes, walls and poison, as well as its
+    /// `skill` allows. It keeps out of portals, its pathfinding doesn't
+    /// know where they lead.
+    pub fn autopilot(&self, i: usize, skill: Skill) -> Option<SnakeDir> {
+        let head = self.snakes[i].head();
+        let mut targets = self.food.clone();
+        targets.extend(self.bonus.map(|(c, _)| c));
+        let blocked = |cell| {
+            self.snakes.iter().any(|s| s.cells.contains(&cell))
+                || self.level.walls.contains(&cell)
+                || self.level.portals.contains_key(&cell)
+                || self.poison.contains(&cell)
+        };
+        match skill {
+            Skill::Smart => {
+                autopilot::next_dir(self.field, self.rules.wrap, head, &targets, blocked)
+            }
+            Skill::Greedy => {
+                autopilot::greedy_dir(self.field, self.rules.wrap, head, &targets, blocked)
+            }
+        }
+    }
+
+    /// Where snake `i` goes this tick, before checking it against the
+    /// other snakes.
+    fn plan(&self, i: usize) -> Move {
+        let snake = &self.snakes[i];
+        let (head_x, head_y) = snake.head();
+        let head_x = head_x as i32;
+        let head_y = head_y as i32;
+
+        let (dx, dy) = lock(&snake.steering).next().delta();
+        let ((x0, y0), (x1, y1)) = self.arena;
+        let passing = self.rules.collision == Collision::Pass;
+        let (wrap_x, wrap_y) = self.rules.wrap;
+        let (wrap_x, wrap_y) = (wrap_x || passing, wrap_y || passing);
+        let new_x = shift(head_x, dx, x0 as i32, x1 as i32, wrap_x);
+        let new_y = shift(head_y, dy, y0 as i32, y1 as i32, wrap_y);
+        // nothing moves on a crash, so where the head would have gone
+        // doesn't matter then
+        let hit_wall = new_x.is_none() || new_y.is_none();
+        let new = match (new_x, new_y) {
+            (Some(x), Some(y)) => (x as usize, y as usize),
+            _ => snake.head(),
+        };
+        // out the other end, still heading the same way. The exit counts as
+        // where the head lands, so a snake lying on it gets hit.
+        let new = self.level.portals.get(&new).copied().unwrap_or(new);
+
+        // a trail is a snake growing on every move
+        let growth = if self.rules.mode == Mode::Tron {
+            1
+        } else if self.food.contains(&new) {
+            self.rules.foods[self.food_kind(new)].growth
+        } else if self.bonus.map(|(c, _)| c) == Some(new) {
+            BONUS_GROWTH
+        } else {
+            0
+        };
+
+        let vacated = if snake.pending_growth + growth > 0 {
+            None
+        } else {
+            snake.bits.first().copied()
+        };
+
+        let new_len = snake.bits.len() + vacated.is_none() as usize;
+        let starved = self.poison.contains(&new) && new_len <= self.rules.poison_penalty;
+
+        let hit_obstacle = !hit_wall && self.level.walls.contains(&new);
+        let hit = if hit_wall {
+            Some(self.on_hit(false))
+        } else if starved {
+            // going through the poison would still leave no snake
+            Some(match self.on_hit(false) {
+                Collision::Pass => Collision::Stop,
+                other => other,
+            })
+        } else if hit_obstacle {
+            Some(self.on_hit(self.rules.solid_walls))
+        } else {
+            None
+        };
+
+        Move {
+            new,
+            vacated,
+            growth,
+            blocked: hit == Some(Collision::Stop),
+            dies: hit == Some(Collision::Crash),
+        }
+    }
+
+    /// What running into something does under the rules, `solid` if it's
+    /// a level wall that stops the snake anyway.
+    fn on_hit(&self, solid: bool) -> Collision {
+        match self.rules.collision {
+            Collision::Crash if solid => Collision::Stop,
+            other => other,
+        }
+    }
+
+    /// Advances the snakes by one cell and returns the cells to redraw.
+    /// Does nothing once the game is over.
+    pub fn step(&mut self) -> Vec<CellUpdate> {
+        let mut updates = Vec::new();
+        if self.game_over {
+            return updates;
+        }
+
+        self.ticks += 1;
+        if self.rules.combo_ticks > 0 {
+            let now = self.ticks;
+            let window = self.rules.combo_ticks as u64;
+            for snake in &mut self.snakes {
+                if snake.last_food.is_some_and(|t| now - t > window) {
+                    snake.combo = 1;
+                }
+            }
+        }
+
+        let mut moves: Vec<_> = (0..self.snakes.len()).map(|i| self.plan(i)).collect();
+
+        // bodies and heads, with every tail that moves on treated as free
+        for i in 0..moves.len() {
+            if moves[i].blocked {
+                continue;
+            }
+            let new = moves[i].new;
+            let ghost = self.snakes[i].ghost_ticks > 0;
+            let hits_body = self
+                .snakes
+                .iter()
+                .zip(&moves)
+                .enumerate()
+                .filter(|&(j, _)| !(j == i && ghost))
+                .any(|(_, (snake, m))| {
+                    let vacated = if m.blocked { None } else { m.vacated };
+                    check_collision(&snake.cells, vacated, new)
+                });
+            let head_on = moves
+                .iter()
+                .enumerate()
+                .any(|(j, m)| j != i && !m.blocked && m.new == new);
+            if hits_body || head_on {
+                match self.on_hit(false) {
+                    Collision::Crash => moves[i].dies = true,
+                    Collision::Stop => moves[i].blocked = true,
+                    Collision::Pass => {}
+                }
+            }
+        }
+
+        // nobody moves on a crash, the game is over if it was someone's
+        // last life and waits for `respawn` otherwise
+        if moves.iter().any(|m| m.dies) {
+            for (i, m) in moves.iter().enumerate() {
+                if m.dies {
+                    self.crash(i, &mut updates);
+                }
+            }
+            return updates;
+        }
+
+        for (i, m) in moves.into_iter().enumerate() {
+            if !m.blocked {
+                self.advance(i, m, &mut updates);
+            }
+        }
+        if self.rules.mode == Mode::Tron {
+            for snake in &mut self.snakes {
+                snake.score += 1;
+            }
+        }
+
+        // the last food is gone and there's nowhere for more to go
+        if self.food.is_empty() && self.out_of_room {
+            self.won = true;
+            self.game_over = true;
+            return updates;
+        }
+
+        if self.rules.food_ttl > 0 {
+            self.age_food(&mut updates);
+        }
+
+        // the bonus vanishes if it isn't eaten in time
+        if let Some(((x, y), ticks)) = self.bonus {
+            if ticks == 0 {
+                updates.push((x, y, Cell::Empty));
+                self.bonus = None;
+            } else {
+                self.bonus = Some(((x, y), ticks - 1));
+            }
+        }
+
+        if self.rules.obstacle_interval > 0 {
+            self.since_dropped += 1;
+            if self.since_dropped >= self.rules.obstacle_interval {
+                self.since_dropped = 0;
+                self.drop_obstacle(&mut updates);
+            }
+        }
+
+        if self.rules.shrink_interval > 0 {
+            self.since_shrink += 1;
+            if self.since_shrink >= self.rules.shrink_interval {
+                self.since_shrink = 0;
+                self.shrink(&mut updates);
+            }
+        }
+
+        updates
+    }
+
+    /// Counts down the time left of every food, moving the ones that run
+    /// out and redrawing the blinking ones.
+    fn age_food(&mut self, updates: &mut Vec<CellUpdate>) {
+        let food = &self.food;
+        self.food_ticks.retain(|cell, _| food.contains(cell));
+
+        let mut expired = Vec::new();
+        for &cell in &self.food {
+            let left = self.food_ticks.entry(cell).or_insert(self.rules.food_ttl);
+            *left = left.saturating_sub(1);
+            if *left == 0 {
+                expired.push(cell);
+            }
+        }
+        for (x, y) in expired {
+            self.food.retain(|&f| f != (x, y));
+            self.food_ticks.remove(&(x, y));
+            self.food_kinds.remove(&(x, y));
+            updates.push((x, y, bare(&self.level, (x, y))));
+        }
+
+        // unchanged food, but the look alternates
+        for &(x, y) in &self.food {
+            if self.food_ticks[&(x, y)] <= FOOD_WARNING_TICKS {
+                updates.push((x, y, self.food_look((x, y))));
+            }
+        }
+        for (x, y) in self.top_up_food() {
+            updates.push((x, y, self.food_look((x, y))));
+        }
+    }
+
+    /// Scores a food worth `points` eaten by snake `i`. They're multiplied
+    /// by the combo, which goes up with every food eaten within
+    /// `combo_ticks` of the previous one and back to 1 once the chain
+    /// breaks.
+    fn score_food(&mut self, i: usize, points: u32) {
+        let now = self.ticks;
+        let window = self.rules.combo_ticks as u64;
+        let snake = &mut self.snakes[i];
+        let chained = window > 0 && snake.last_food.is_some_and(|t| now - t <= window);
+        snake.combo = if chained {
+            (snake.combo + 1).min(MAX_COMBO)
+        } else {
+            1
+        };
+        snake.last_food = Some(now);
+        snake.score += snake.combo * points;
+    }
+
+    /// Snake `i` hit something, it stays on the board as a wreck.
+    fn crash(&mut self, i: usize, updates: &mut Vec<CellUpdate>) {
+        log::info!("Snake {} crashed at {:?}", i + 1, self.snakes[i].head());
+        let snake = &mut self.snakes[i];
+        snake.alive = false;
+        snake.lives = snake.lives.saturating_sub(1);
+        self.game_over |= snake.lives == 0;
+        updates.extend(snake.bits.iter().map(|&(x, y)| (x, y, Cell::Dead)));
+    }
+
+    /// Walls off the outermost ring of the arena, unless that leaves it
+    /// smaller than the rules allow. Items in the ring are lost, food
+    /// comes back further in, and a snake caught in it crashes.
+    fn shrink(&mut self, updates: &mut Vec<CellUpdate>) {
+        let ((x0, y0), (x1, y1)) = self.arena;
+        let (w, h) = (x1 - x0, y1 - y0);
+        if w <= 2 || h <= 2 || (w - 2) * (h - 2) < self.rules.shrink_min_area {
+            return;
+        }
+
+        let ring: HashSet<_> = (y0..y1)
+            .flat_map(|y| (x0..x1).map(move |x| (x, y)))
+            .filter(|&(x, y)| x == x0 || x == x1 - 1 || y == y0 || y == y1 - 1)
+            .collect();
+        self.arena = ((x0 + 1, y0 + 1), (x1 - 1, y1 - 1));
+        for &(x, y) in &ring {
+            // level walls already there stay when the arena grows back
+            if self.level.walls.insert((x, y)) {
+                self.obstacles.push((x, y));
+            }
+            updates.push((x, y, Cell::Wall));
+        }
+
+        self.food.retain(|f| !ring.contains(f));
+        self.poison.retain(|p| !ring.contains(p));
+        if self.bonus.is_some_and(|(c, _)| ring.contains(&c)) {
+            self.bonus = None;
+        }
+        if self.ghost.is_some_and(|c| ring.contains(&c)) {
+            self.ghost = None;
+        }
+        if self.confusion.is_some_and(|c| ring.contains(&c)) {
+            self.confusion = None;
+        }
+        if self.slow.is_some_and(|c| ring.contains(&c)) {
+            self.slow = None;
+        }
+        if self.shrink.is_some_and(|c| ring.contains(&c)) {
+            self.shrink = None;
+        }
+        for (x, y) in self.top_up_food() {
+            updates.push((x, y, self.food_look((x, y))));
+        }
+
+        for i in 0..self.snakes.len() {
+            if self.snakes[i].cells.iter().any(|c| ring.contains(c)) {
+                self.crash(i, updates);
+            }
+        }
+    }
+
+    /// Counts `ms` of play towards the survival timer, adding obstacles on
+    /// the way, and returns the cells to redraw. Ends the game with a win
+    /// once the time is up.
+    pub fn elapse(&mut self, ms: u64) -> Vec<CellUpdate> {
+        let mut updates = Vec::new();
+        if self.rules.mode != Mode::Survival || self.game_over {
+            return updates;
+        }
+
+        self.time_left_ms = self.time_left_ms.saturating_sub(ms);
+        if self.time_left_ms == 0 {
+            self.won = true;
+            self.game_over = true;
+            return updates;
+        }
+
+        if self.rules.obstacle_ms == 0 {
+            return updates;
+        }
+        self.since_obstacle_ms += ms;
+        while self.since_obstacle_ms >= self.rules.obstacle_ms {
+            self.since_obstacle_ms -= self.rules.obstacle_ms;
+            self.drop_obstacle(&mut updates);
+        }
+        updates
+    }
+
+    /// Adds a wall on a random free cell, unless there are `max_obstacles`
+    /// already.
+    fn drop_obstacle(&mut self, updates: &mut Vec<CellUpdate>) {
+        if self.rules.max_obstacles > 0 && self.dropped >= self.rules.max_obstacles {
+            return;
+        }
+        // never right in front of a snake, there'd be no way to dodge it
+        let heads: Vec<_> = self.snakes.iter().map(|s| s.head()).collect();
+        let far = |(x, y): (usize, usize)| {
+            heads
+                .iter()
+                .all(|&(hx, hy)| x.abs_diff(hx) + y.abs_diff(hy) > 2)
+        };
+        if let Some((x, y)) = self.spawn_where(far) {
+            self.level.walls.insert((x, y));
+            self.obstacles.push((x, y));
+            self.dropped += 1;
+            updates.push((x, y, Cell::Wall));
+        }
+    }
+
+    /// Moves snake `i` along `m` and eats whatever is there.
+    fn advance(&mut self, i: usize, m: Move, updates: &mut Vec<CellUpdate>) {
+        let snake = &mut self.snakes[i];
+        let looked = snake.body_look(i);
+        snake.ghost_ticks = snake.ghost_ticks.saturating_sub(1);
+        if snake.confused_ticks > 0 {
+            snake.confused_ticks -= 1;
+            lock(&snake.steering).confused = snake.confused_ticks > 0;
+        }
+
+        snake.pending_growth += m.growth;
+        if snake.pending_growth > 0 {
+            snake.pending_growth -= 1;
+        } else {
+            let tail = snake.bits.remove(0);
+            if snake.forget(tail) {
+                updates.push((tail.0, tail.1, bare(&self.level, tail)));
+            }
+        }
+
+        // the old head becomes body, unless it was also the tail
+        snake.bits.push(m.new);
+        snake.cells.insert(m.new);
+        if let [.., (x, y), _] = snake.bits[..] {
+            updates.push((x, y, snake.look(i, (x, y))));
+        }
+        updates.push((m.new.0, m.new.1, Cell::Head(i)));
+
+        if let Some(eaten) = self.food.iter().position(|&f| f == m.new) {
+            let kind = self.food_kinds.remove(&m.new).unwrap_or(0);
+            self.score_food(i, self.rules.foods[kind].score);
+            self.foods_eaten += 1;
+            log::info!(
+                "Snake {} ate food at {:?}, score {}",
+                i + 1,
+                m.new,
+                self.snakes[i].score
+            );
+            self.food.swap_remove(eaten);
+            for (x, y) in self.top_up_food() {
+                updates.push((x, y, self.food_look((x, y))));
+            }
+
+            if self.rng.gen::<f64>() < self.rules.poison_chance {
+                if let Some((x, y)) = self.spawn_food() {
+                    self.poison.push((x, y));
+                    updates.push((x, y, Cell::Poison));
+                }
+            }
+
+            if self.ghost.is_none() && self.rng.gen::<f64>() < self.rules.ghost_chance {
+                self.ghost = self.spawn_food();
+                updates.extend(self.ghost.map(|(x, y)| (x, y, Cell::GhostFood)));
+            }
+
+            if self.confusion.is_none() && self.rng.gen::<f64>() < self.rules.confusion_chance {
+                self.confusion = self.spawn_food();
+                updates.extend(self.confusion.map(|(x, y)| (x, y, Cell::ConfusionFood)));
+            }
+
+            if self.slow.is_none() && self.rng.gen::<f64>() < self.rules.slow_chance {
+                self.slow = self.spawn_food();
+                updates.extend(self.slow.map(|(x, y)| (x, y, Cell::SlowFood)));
+            }
+
+            if self.shrink.is_none() && self.rng.gen::<f64>() < self.rules.shrink_chance {
+                self.shrink = self.spawn_food();
+                updates.extend(self.shrink.map(|(x, y)| (x, y, Cell::ShrinkFood)));
+            }
+
+            if self.rules.bonus_interval > 0
+                && self.foods_eaten.is_multiple_of(self.rules.bonus_interval)
+                && self.bonus.is_none()
+            {
+                if let Some((x, y)) = self.spawn_food() {
+                    self.bonus = Some(((x, y), self.rules.bonus_ticks));
+                    updates.push((x, y, Cell::Bonus));
+                }
+            }
+        } else if self.bonus.map(|(c, _)| c) == Some(m.new) {
+            self.snakes[i].score += self.rules.bonus_score;
+            self.bonuses_eaten += 1;
+            self.bonus = None;
+        } else if let Some(poisoned) = self.poison.iter().position(|&p| p == m.new) {
+            self.poison.swap_remove(poisoned);
+            let snake = &mut self.snakes[i];
+            let lost: Vec<_> = snake.bits.drain(..self.rules.poison_penalty).collect();
+            for cell in lost {
+                if snake.forget(cell) {
+                    updates.push((cell.0, cell.1, bare(&self.level, cell)));
+                }
+            }
+        } else if self.ghost == Some(m.new) {
+            self.ghost = None;
+            self.snakes[i].ghost_ticks = self.rules.ghost_ticks;
+        } else if self.confusion == Some(m.new) {
+            self.confusion = None;
+            let snake = &mut self.snakes[i];
+            snake.confused_ticks = self.rules.confusion_ticks;
+            lock(&snake.steering).confused = snake.confused_ticks > 0;
+        } else if self.slow == Some(m.new) {
+            self.slow = None;
+            // another one while slowed starts the time over, it doesn't add up
+            self.slow_until_tick = self.ticks + self.rules.slow_ticks as u64;
+        } else if self.shrink == Some(m.new) {
+            self.shrink = None;
+            let snake = &mut self.snakes[i];
+            let cut = self.rules.shrink_segments.min(snake.bits.len() - 1);
+            let lost: Vec<_> = snake.bits.drain(..cut).collect();
+            for cell in lost {
+                if snake.forget(cell) {
+                    updates.push((cell.0, cell.1, bare(&self.level, cell)));
+                }
+            }
+        }
+
+        // going ghost, confused or back changes how the whole body looks
+        let snake = &self.snakes[i];
+        if snake.body_look(i) != looked {
+            updates.extend(snake.drawn(i));
+        }
+    }
+}
+
+/// Picks a cell of the `field` (width, height) that isn't `taken`,
+/// uniformly among the free ones, or `None` if there are none.
+///
+/// A few random guesses are all it takes on a mostly empty board; once the
+/// snake fills most of it we fall back to listing the free cells, so this
+/// never spins forever near the end of a game.
+pub fn spawn_food(
+    taken: impl Fn((usize, usize)) -> bool,
+    field: (usize, usize),
+    rng: &mut impl Rng,
+) -> Option<(usize, usize)> {
+    let (width, height) = field;
+    if width == 0 || height == 0 {
+        return None;
+    }
+
+    for _ in 0..SPAWN_ATTEMPTS {
+        let cell = (rng.gen_range(0..width), rng.gen_range(0..height));
+        if !taken(cell) {
+            return Some(cell);
+        }
+    }
+
+    let free: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&cell| !taken(cell))
+        .collect();
+    free.choose(rng).copied()
+}
+
+/// A snake of up to `len` cells with its head at `head`, heading `dir`,
+/// head first. It's cut short at the edge of the `field`.
+pub fn body_from(
+    head: (usize, usize),
+    dir: SnakeDir,
+    len: usize,
+    field: (usize, usize),
+) -> Vec<(usize, usize)> {
+    let (dx, dy) = dir.opposite().delta();
+    let (width, height) = (field.0 as i32, field.1 as i32);
+    (0..len as i32)
+        .map(|k| (head.0 as i32 + k * dx, head.1 as i32 + k * dy))
+        .take_while(|&(x, y)| (0..width).contains(&x) && (0..height).contains(&y))
+        .map(|(x, y)| (x as usize, y as usize))
+        .collect()
+}
+
+/// What's left at `cell` once the snake is gone from it. An invincible
+/// snake can pass through walls, which are still there after it.
+fn bare(level: &Level, cell: (usize, usize)) -> Cell {
+    if level.walls.contains(&cell) {
+        Cell::Wall
+    } else if level.portals.contains_key(&cell) {
+        Cell::Portal
+    } else {
+        Cell::Empty
+    }
+}
+
+/// `val` moved by `delta` along an axis from `min` to `max` (exclusive).
+/// Past either end it comes back in at the other one if it `wraps`, and is
+/// `None` otherwise.
+fn shift(val: i32, delta: i32, min: i32, max: i32, wraps: bool) -> Option<i32> {
+    let val = val + delta;
+    if (min..max).contains(&val) {
+        Some(val)
+    } else if !wraps {
+        None
+    } else if val < min {
+        Some(max - 1)
+    } else {
+        Some(min)
+    }
+}
+
+fn check_collision(
+    snake_cells: &HashSet<(usize, usize)>,
+    vacated: Option<(usize, usize)>,
+    new_bit: (usize, usize),
+) -> bool {
+    snake_cells.contains(&new_bit) && vacated != Some(new_bit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    type Pos = (usize, usize);
+
+    /// Plain classic rules, one food and no power-ups.
+    fn rules() -> Rules {
+        Rules {
+            food_count: 1,
+            foods: vec![FoodKind {
+                growth: 1,
+                score: 1,
+                weight: 1,
+            }],
+            wrap: (true, true),
+            solid_walls: false,
+            collision: Collision::Crash,
+            bonus_interval: 0,
+            bonus_ticks: 0,
+            bonus_score: 0,
+            poison_chance: 0.0,
+            poison_penalty: 0,
+            ghost_chance: 0.0,
+            ghost_ticks: 0,
+            confusion_chance: 0.0,
+            confusion_ticks: 0,
+            slow_chance: 0.0,
+            slow_ticks: 0,
+            shrink_chance: 0.0,
+            shrink_segments: 0,
+            mode: Mode::Classic,
+            survival_ms: 0,
+            obstacle_ms: 0,
+            obstacle_interval: 0,
+            max_obstacles: 0,
+            lives: 1,
+            start_dir: SnakeDir::Right,
+            start_length: 1,
+            shrink_interval: 0,
+            shrink_min_area: 0,
+            food_ttl: 0,
+            combo_ticks: 0,
+            opponent: false,
+        }
+    }
+
+    /// A one player game on `level`, with the steering to drive it.
+    fn game(
+        rules: Rules,
+        field: (usize, usize),
+        level: Level,
+    ) -> (GameState, Arc<Mutex<Steering>>) {
+        let steering = Arc::new(Mutex::new(Steering::new(rules.start_dir, 3)));
+        let rng = StdRng::seed_from_u64(0);
+        let state = GameState::new(rules, field, level, vec![steering.clone()], rng);
+        (state, steering)
+    }
+
+    fn level_at(start: (usize, usize)) -> Level {
+        Level {
+            start,
+            food: Vec::new(),
+            ..Level::empty()
+        }
+    }
+
+    /// A single cell snake at `start` on a 5x4 field after one tick
+    /// heading `dir`, with the edges wrapping or not.
+    fn off_edge(wraps: bool, start: (usize, usize), dir: SnakeDir) -> GameState {
+        let rules = Rules {
+            wrap: (wraps, wraps),
+            start_dir: dir,
+            ..rules()
+        };
+        let (mut state, _) = game(rules, (5, 4), level_at(start));
+        state.step();
+        state
+    }
+
+    // start, heading and where wrapping comes back in, one per edge
+    const EDGES: [(Pos, SnakeDir, Pos); 4] = [
+        ((2, 0), SnakeDir::Up, (2, 3)),
+        ((2, 3), SnakeDir::Down, (2, 0)),
+        ((0, 1), SnakeDir::Left, (4, 1)),
+        ((4, 1), SnakeDir::Right, (0, 1)),
+    ];
+
+    #[test]
+    fn edges_wrap_around() {
+        for (start, dir, wrapped) in EDGES {
+            let state = off_edge(true, start, dir);
+            assert!(!state.game_over, "crashed going {:?}", dir);
+            assert_eq!(state.snakes[0].head(), wrapped, "going {:?}", dir);
+        }
+    }
+
+    #[test]
+    fn edges_are_walls() {
+        for (start, dir, _) in EDGES {
+            let state = off_edge(false, start, dir);
+            assert!(!state.snakes[0].alive, "survived going {:?}", dir);
+            assert!(state.game_over, "going {:?}", dir);
+            assert_eq!(state.snakes[0].head(), start, "going {:?}", dir);
+        }
+    }
+
+    #[test]
+    fn a_wall_passed_through_stays_a_wall() {
+        let rules = Rules {
+            food_count: 0,
+            collision: Collision::Pass,
+            ..rules()
+        };
+        let level = Level {
+            walls: [(2, 1)].into(),
+            ..level_at((1, 1))
+        };
+        let (mut state, _) = game(rules, (5, 3), level);
+        state.step();
+        assert_eq!(state.snakes[0].head(), (2, 1));
+        let updates = state.step();
+        assert!(updates.contains(&(2, 1, Cell::Wall)));
+        assert_eq!(state.cell((2, 1)), Cell::Wall);
+    }
+
+    #[test]
+    fn no_room_for_food_is_a_win() {
+        let level = Level {
+            food: vec![(1, 0)],
+            ..level_at((0, 0))
+        };
+        let (mut state, _) = game(rules(), (3, 1), level);
+        // the only cell left after eating, so no food can go anywhere
+        state.poison.push((2, 0));
+        state.step();
+        assert!(state.food.is_empty());
+        assert!(state.won);
+        assert!(state.game_over);
+    }
+
+    #[test]
+    fn filling_a_tiny_board_is_a_win() {
+        let rules = Rules {
+            start_dir: SnakeDir::Left,
+            ..rules()
+        };
+        let (mut state, steering) = game(rules, (2, 2), Level::empty());
+        // round and round the board, eating whatever is in the way
+        for _ in 0..8 {
+            let dir = match state.snakes[0].head() {
+                (1, 1) => SnakeDir::Left,
+                (0, 1) => SnakeDir::Up,
+                (0, 0) => SnakeDir::Right,
+                _ => SnakeDir::Down,
+            };
+            lock(&steering).press(dir);
+            state.step();
+            if state.game_over {
+                break;
+            }
+        }
+        assert!(state.won);
+        assert_eq!(state.snakes[0].cells.len(), 4);
+    }
+
+    #[test]
+    fn no_food_under_the_starting_body() {
+        let rules = Rules {
+            start_dir: SnakeDir::Left,
+            start_length: 2,
+            ..rules()
+        };
+        // the empty level's food is right behind its start
+        let (state, _) = game(rules, (8, 8), Level::empty());
+        assert_eq!(state.food.len(), 1);
+        assert!(!state.snakes[0].cells.contains(&state.food[0]));
+    }
+
+    #[test]
+    fn up_then_down_in_one_tick_does_not_reverse() {
+        let rules = Rules {
+            start_length: 3,
+            ..rules()
+        };
+        let (mut state, steering) = game(rules, (8, 8), level_at((4, 4)));
+        lock(&steering).press(SnakeDir::Up);
+        lock(&steering).press(SnakeDir::Down);
+        state.step();
+        assert!(state.snakes[0].alive);
+        assert_eq!(state.snakes[0].head(), (4, 3));
+
+        // the down press didn't wait for the next tick either
+        state.step();
+        assert!(state.snakes[0].alive);
+        assert_eq!(state.snakes[0].head(), (4, 2));
+    }
+
+    #[test]
+    fn reversal_in_the_queue_is_skipped() {
+        let mut steering = Steering::new(SnakeDir::Right, 3);
+        // bypasses the check `push` makes, as if it slipped in somehow
+        steering.queue.extend([SnakeDir::Left, SnakeDir::Up]);
+        assert_eq!(steering.next(), SnakeDir::Up);
+        assert_eq!(steering.next(), SnakeDir::Up);
+    }
+
+    #[test]
+    fn collision_with_the_body() {
+        let cells: HashSet<Pos> = [(1, 1), (2, 1), (3, 1)].into();
+        assert!(check_collision(&cells, None, (2, 1)));
+        assert!(check_collision(&cells, Some((3, 1)), (2, 1)));
+        assert!(!check_collision(&cells, None, (2, 2)));
+    }
+
+    #[test]
+    fn the_tail_moving_away_is_no_collision() {
+        let cells: HashSet<Pos> = [(1, 1), (2, 1), (2, 2), (1, 2)].into();
+        assert!(!check_collision(&cells, Some((1, 2)), (1, 2)));
+        // unless it stays put, growing
+        assert!(check_collision(&cells, None, (1, 2)));
+    }
+
+    #[test]
+    fn food_never_lands_on_a_taken_cell() {
+        let mut rng = StdRng::seed_from_u64(7);
+        // everything but one column, so the guesses mostly miss
+        let taken = |(x, _): Pos| x != 3;
+        for _ in 0..200 {
+            let (x, y) = spawn_food(taken, (5, 4), &mut rng).unwrap();
+            assert_eq!(x, 3);
+            assert!(y < 4);
+        }
+    }
+
+    #[test]
+    fn no_food_on_a_full_board() {
+        let mut rng = StdRng::seed_from_u64(7);
+        assert_eq!(spawn_food(|_| true, (5, 4), &mut rng), None);
+        assert_eq!(spawn_food(|_| false, (0, 4), &mut rng), None);
+    }
+
+    #[test]
+    fn the_last_free_cell_is_found() {
+        let mut rng = StdRng::seed_from_u64(7);
+        let taken = |cell: Pos| cell != (4, 3);
+        assert_eq!(spawn_food(taken, (5, 4), &mut rng), Some((4, 3)));
+    }
+
+    #[test]
+    fn combo_grows_caps_and_lapses() {
+        let rules = Rules {
+            food_count: 0,
+            combo_ticks: 3,
+            ..rules()
+        };
+        let (mut state, _) = game(rules, (8, 8), level_at((1, 1)));
+        let mut combos = Vec::new();
+        for _ in 0..7 {
+            state.ticks += 1;
+            state.score_food(0, 1);
+            combos.push(state.snakes[0].combo);
+        }
+        assert_eq!(combos, [1, 2, 3, 4, 5, 5, 5]);
+        assert_eq!(state.snakes[0].score, 1 + 2 + 3 + 4 + 5 + 5 + 5);
+
+        // the last food of the window still chains
+        state.ticks += 3;
+        state.score_food(0, 1);
+        assert_eq!(state.snakes[0].combo, MAX_COMBO);
+
+        // a tick past it, moving on is enough to break the chain
+        for _ in 0..4 {
+            state.step();
+        }
+        assert_eq!(state.snakes[0].combo, 1);
+        state.score_food(0, 1);
+        assert_eq!(state.snakes[0].combo, 1);
+    }
+}
@@ -0,0 +1,75 @@
+use crate::game::{self, SnakeDir, Steering};
+use crate::input::Flags;
+use gilrs::{Axis, Button, EventType, Gilrs};
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+
+// how far the stick has to go to count as a turn
+const STICK_THRESHOLD: f32 = 0.6;
+// and how close to the center it has to come back before the next one
+const STICK_DEADZONE: f32 = 0.3;
+
+/// Steers with the D-pad and left stick of any connected controller, from a
+/// thread of its own.
+pub fn spawn(steering: Arc<Mutex<Steering>>, flags: Arc<Flags>) {
+    std::thread::spawn(move || {
+        let mut gilrs = match Gilrs::new() {
+            Ok(gilrs) => gilrs,
+            Err(error) => {
+                log::warn!("Gamepad support unavailable: {}", error);
+                return;
+            }
+        };
+
+        let mut stick = Stick::default();
+        while let Some(event) = gilrs.next_event_blocking(None) {
+            let dir = match event.event {
+                EventType::ButtonPressed(button, _) => dpad_dir(button),
+                EventType::AxisChanged(axis, value, _) => stick.moved(axis, value),
+                _ => None,
+            };
+            if let Some(dir) = dir {
+                flags.manual.store(true, Ordering::SeqCst);
+                game::lock(&steering).press(dir);
+            }
+        }
+    });
+}
+
+fn dpad_dir(button: Button) -> Option<SnakeDir> {
+    match button {
+        Button::DPadUp => Some(SnakeDir::Up),
+        Button::DPadDown => Some(SnakeDir::Down),
+        Button::DPadLeft => Some(SnakeDir::Left),
+        Button::DPadRight => Some(SnakeDir::Right),
+        _ => None,
+    }
+}
+
+/// Which axes of the left stick are currently pushed past the threshold.
+#[derive(Default)]
+struct Stick {
+    x: bool,
+    y: bool,
+}
+
+impl Stick {
+    /// A turn when an axis crosses the threshold, nothing while it stays
+    /// there.
+    fn moved(&mut self, axis: Axis, value: f32) -> Option<SnakeDir> {
+        let (pushed, dir) = match axis {
+            Axis::LeftStickX => (&mut self.x, [SnakeDir::Left, SnakeDir::Right]),
+            // gilrs has up as positive
+            Axis::LeftStickY => (&mut self.y, [SnakeDir::Down, SnakeDir::Up]),
+            _ => return None,
+        };
+
+        if value.abs() < STICK_DEADZONE {
+            *pushed = false;
+        } else if value.abs() > STICK_THRESHOLD && !*pushed {
+            *pushed = true;
+            return Some(dir[(value > 0.0) as usize]);
+        }
+        None
+    }
+}
@@ -0,0 +1,27 @@
+use std::path::Path;
+
+/// Reads the best score saved at `path`. A missing file means nobody has
+/// played yet; a corrupt one is reported and treated the same way.
+pub fn load_high_score(path: &Path) -> u32 {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(_) => return 0,
+    };
+
+    match contents.trim().parse() {
+        Ok(score) => score,
+        Err(_) => {
+            log::warn!("ignoring corrupt high score file {}", path.display());
+            0
+        }
+    }
+}
+
+/// Writes to a temporary file first and renames it over the old one, so a
+/// crash mid-write can't leave a truncated record behind.
+pub fn save_high_score(path: &Path, score: u32) -> anyhow::Result<()> {
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, score.to_string())?;
+    std::fs::rename(&tmp, path)?;
+    Ok(())
+}
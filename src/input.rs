@@ -0,0 +1,328 @@
+use crate::game::SnakeDir;
+use rdev::Key;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Controls {
+    Arrows,
+    Wasd,
+    Hjkl,
+    Both,
+    #[default]
+    All,
+    /// No direction keys, the snake turns towards the mouse cursor.
+    Mouse,
+}
+
+impl Controls {
+    pub fn accepts(self, key: Key) -> bool {
+        use Controls::*;
+        use Key::*;
+        match key {
+            UpArrow | DownArrow | LeftArrow | RightArrow => matches!(self, Arrows | Both | All),
+            KeyW | KeyA | KeyS | KeyD => matches!(self, Wasd | Both | All),
+            KeyH | KeyJ | KeyK | KeyL => matches!(self, Hjkl | All),
+            _ => true,
+        }
+    }
+}
+
+impl TryFrom<Key> for SnakeDir {
+    type Error = ();
+    fn try_from(key: Key) -> Result<Self, Self::Error> {
+        match key {
+            Key::UpArrow | Key::KeyW | Key::KeyK => Ok(SnakeDir::Up),
+            Key::DownArrow | Key::KeyS | Key::KeyJ => Ok(SnakeDir::Down),
+            Key::LeftArrow | Key::KeyA | Key::KeyH => Ok(SnakeDir::Left),
+            Key::RightArrow | Key::KeyD | Key::KeyL => Ok(SnakeDir::Right),
+            _ => Err(()),
+        }
+    }
+}
+
+/// The `[keys]` table, with key names as rdev spells them ("UpArrow",
+/// "KeyZ", "Escape", ...). A direction left out keeps the keys picked by
+/// `controls`; one that is set only answers to its own key.
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct Keys {
+    pub up: Option<Key>,
+    pub down: Option<Key>,
+    pub left: Option<Key>,
+    pub right: Option<Key>,
+    /// `[keys.player2]`, only used with two players. They take over from
+    /// the first player's keys, so J, K and L stop meaning vim directions.
+    pub player2: PlayerKeys,
+    pub pause: Key,
+    pub restart: Key,
+    pub quit: Key,
+    /// Held down to move faster, see `boost_ms`.
+    pub boost: Key,
+}
+
+impl Default for Keys {
+    fn default() -> Keys {
+        Keys {
+            up: None,
+            down: None,
+            left: None,
+            right: None,
+            player2: PlayerKeys::default(),
+            pause: Key::Space,
+            restart: Key::KeyR,
+            quit: Key::Escape,
+            boost: Key::ShiftLeft,
+        }
+    }
+}
+
+impl Keys {
+    /// The direction `key` steers towards, if any.
+    pub fn steer(&self, key: Key, controls: Controls) -> Option<SnakeDir> {
+        let bound = [
+            (self.up, SnakeDir::Up),
+            (self.down, SnakeDir::Down),
+            (self.left, SnakeDir::Left),
+            (self.right, SnakeDir::Right),
+        ];
+        if let Some(&(_, dir)) = bound.iter().find(|(k, _)| *k == Some(key)) {
+            return Some(dir);
+        }
+
+        let dir = SnakeDir::try_from(key)
+            .ok()
+            .filter(|_| controls.accepts(key))?;
+        let own_key = match dir {
+            SnakeDir::Up => self.up,
+            SnakeDir::Down => self.down,
+            SnakeDir::Left => self.left,
+            SnakeDir::Right => self.right,
+        };
+        own_key.is_none().then_some(dir)
+    }
+}
+
+/// A set of four direction keys, IJKL unless configured.
+#[derive(Serialize, Deserialize, Copy, Clone)]
+#[serde(default)]
+pub struct PlayerKeys {
+    pub up: Key,
+    pub down: Key,
+    pub left: Key,
+    pub right: Key,
+}
+
+impl Default for PlayerKeys {
+    fn default() -> PlayerKeys {
+        PlayerKeys {
+            up: Key::KeyI,
+            down: Key::KeyK,
+            left: Key::KeyJ,
+            right: Key::KeyL,
+        }
+    }
+}
+
+impl PlayerKeys {
+    pub fn steer(&self, key: Key) -> Option<SnakeDir> {
+        [
+            (self.up, SnakeDir::Up),
+            (self.down, SnakeDir::Down),
+            (self.left, SnakeDir::Left),
+            (self.right, SnakeDir::Right),
+        ]
+        .into_iter()
+        .find(|&(k, _)| k == key)
+        .map(|(_, dir)| dir)
+    }
+}
+
+/// Where to turn to get from `from` to `to` on screen: along the axis
+/// they're furthest apart on. `None` once they're within `near` of each
+/// other on both axes.
+pub fn toward(from: (f64, f64), to: (f64, f64), near: (f64, f64)) -> Option<SnakeDir> {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    if dx.abs() < near.0 && dy.abs() < near.1 {
+        None
+    } else if dx.abs() >= dy.abs() {
+        Some(if dx < 0.0 {
+            SnakeDir::Left
+        } else {
+            SnakeDir::Right
+        })
+    } else {
+        Some(if dy < 0.0 {
+            SnakeDir::Up
+        } else {
+            SnakeDir::Down
+        })
+    }
+}
+
+/// Whether `key` is one of the arrow keys.
+pub fn is_arrow(key: Key) -> bool {
+    matches!(
+        key,
+        Key::UpArrow | Key::DownArrow | Key::LeftArrow | Key::RightArrow
+    )
+}
+
+/// What the keyboard thread asks of the game loop.
+#[derive(Default)]
+pub struct Flags {
+    pub restart: AtomicBool,
+    pub paused: AtomicBool,
+    pub quit: AtomicBool,
+    /// Someone steered by hand, the autopilot is off for good.
+    pub manual: AtomicBool,
+    /// The boost key is held down.
+    pub boosting: AtomicBool,
+    /// Last known mouse position on screen, for mouse controls.
+    pub cursor: Mutex<Option<(f64, f64)>>,
+}
+
+impl Flags {
+    /// Updates the flags for a key press. Returns whether the key may still
+    /// steer the snake.
+    pub fn press(&self, key: Key, keys: &Keys) -> bool {
+        // nothing else matters once we're on our way out
+        if self.quit.load(Ordering::SeqCst) {
+            return false;
+        }
+
+        if key == keys.quit {
+            self.quit.store(true, Ordering::SeqCst);
+            return false;
+        }
+        if key == keys.restart {
+            // a fresh game doesn't start paused
+            self.restart.store(true, Ordering::SeqCst);
+            if self.paused.swap(false, Ordering::SeqCst) {
+                log::info!("Resumed");
+            }
+        } else if key == keys.pause {
+            let was_paused = self.paused.fetch_xor(true, Ordering::SeqCst);
+            log::info!("{}", if was_paused { "Resumed" } else { "Paused" });
+        }
+
+        // letters are probably someone typing elsewhere while paused
+        is_arrow(key) || !self.paused.load(Ordering::SeqCst)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETS: [(Controls, [bool; 3]); 5] = [
+        (Controls::Arrows, [true, false, false]),
+        (Controls::Wasd, [false, true, false]),
+        (Controls::Hjkl, [false, false, true]),
+        (Controls::Both, [true, true, false]),
+        (Controls::All, [true, true, true]),
+    ];
+
+    #[test]
+    fn each_set_steers_under_its_controls() {
+        let keys = Keys::default();
+        // arrows, WASD and HJKL for up, down, left and right
+        let sets = [
+            [
+                Key::UpArrow,
+                Key::DownArrow,
+                Key::LeftArrow,
+                Key::RightArrow,
+            ],
+            [Key::KeyW, Key::KeyS, Key::KeyA, Key::KeyD],
+            [Key::KeyK, Key::KeyJ, Key::KeyH, Key::KeyL],
+        ];
+        let dirs = [
+            SnakeDir::Up,
+            SnakeDir::Down,
+            SnakeDir::Left,
+            SnakeDir::Right,
+        ];
+        for (controls, on) in SETS {
+            for (set, on) in sets.iter().zip(on) {
+                for (&key, dir) in set.iter().zip(dirs) {
+                    assert_eq!(controls.accepts(key), on);
+                    assert_eq!(keys.steer(key, controls), on.then_some(dir));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn mouse_controls_take_no_direction_keys() {
+        let keys = Keys::default();
+        for key in [Key::UpArrow, Key::KeyW, Key::KeyK] {
+            assert_eq!(keys.steer(key, Controls::Mouse), None);
+        }
+    }
+
+    #[test]
+    fn a_bound_key_replaces_the_default_ones() {
+        let keys = Keys {
+            up: Some(Key::KeyZ),
+            ..Keys::default()
+        };
+        for (controls, _) in SETS {
+            assert_eq!(keys.steer(Key::KeyZ, controls), Some(SnakeDir::Up));
+            assert_eq!(keys.steer(Key::UpArrow, controls), None);
+            assert_eq!(keys.steer(Key::KeyW, controls), None);
+            assert_eq!(keys.steer(Key::KeyK, controls), None);
+        }
+        // the other directions keep theirs
+        assert_eq!(
+            keys.steer(Key::DownArrow, Controls::All),
+            Some(SnakeDir::Down)
+        );
+    }
+
+    fn paused(flags: &Flags) -> bool {
+        flags.paused.load(Ordering::SeqCst)
+    }
+
+    #[test]
+    fn pause_toggles() {
+        let (flags, keys) = (Flags::default(), Keys::default());
+        flags.press(Key::Space, &keys);
+        assert!(paused(&flags));
+        flags.press(Key::Space, &keys);
+        assert!(!paused(&flags));
+    }
+
+    #[test]
+    fn only_arrows_steer_while_paused() {
+        let (flags, keys) = (Flags::default(), Keys::default());
+        assert!(flags.press(Key::KeyW, &keys));
+        flags.press(Key::Space, &keys);
+        assert!(!flags.press(Key::KeyW, &keys));
+        assert!(!flags.press(Key::KeyH, &keys));
+        assert!(flags.press(Key::UpArrow, &keys));
+    }
+
+    #[test]
+    fn restart_unpauses() {
+        let (flags, keys) = (Flags::default(), Keys::default());
+        flags.press(Key::Space, &keys);
+        flags.press(Key::KeyR, &keys);
+        assert!(flags.restart.load(Ordering::SeqCst));
+        assert!(!paused(&flags));
+    }
+
+    #[test]
+    fn nothing_counts_after_quit() {
+        let (flags, keys) = (Flags::default(), Keys::default());
+        assert!(!flags.press(Key::Escape, &keys));
+        assert!(flags.quit.load(Ordering::SeqCst));
+        assert!(!flags.press(Key::UpArrow, &keys));
+        assert!(!flags.press(Key::Space, &keys));
+        assert!(!paused(&flags));
+        assert!(!flags.press(Key::KeyR, &keys));
+        assert!(!flags.restart.load(Ordering::SeqCst));
+    }
+}
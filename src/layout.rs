@@ -0,0 +1,185 @@
+use anyhow::Context;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use windows::core::{Interface, Vtable, PWSTR};
+use windows::Win32::Foundation::{E_FAIL, POINT, RECT};
+use windows::Win32::System::Com::{
+    CoCreateInstance, CoInitializeEx, CoTaskMemFree, IServiceProvider, CLSCTX_ALL,
+    COINIT_APARTMENTTHREADED, VARIANT, VT_I4,
+};
+use windows::Win32::UI::Shell::Common::ITEMIDLIST;
+use windows::Win32::UI::Shell::{
+    IFolderView, IShellBrowser, IShellFolder, IShellWindows, SID_STopLevelBrowser, ShellWindows,
+    StrRetToStrW, CSIDL_DESKTOP, SHGDNF, SHGDN_FORPARSING, SHGDN_INFOLDER, SVGIO_ALLVIEW,
+    SVSI_POSITIONITEM, SWC_DESKTOP, SWFO_NEEDDISPATCH,
+};
+use windows::Win32::UI::WindowsAndMessaging::{
+    SystemParametersInfoW, SPI_GETWORKAREA, SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS,
+};
+
+/// Where each desktop icon is, in pixels, by file name.
+type Layout = HashMap<String, (i32, i32)>;
+
+// the backup `restore` reads, once `backup` has made it
+static BACKUP: OnceLock<PathBuf> = OnceLock::new();
+
+/// Where the icon layout is kept while the game runs.
+pub fn default_path() -> Option<PathBuf> {
+    directories::ProjectDirs::from("", "", "desktop-snake")
+        .map(|dirs| dirs.data_dir().join("desktop-layout.json"))
+}
+
+/// Saves where the icons on the desktop are to `path`, before the game
+/// spreads its own over them. A backup already there is from a run that
+/// never got to restore it, so it's kept: it has the layout from before
+/// that run.
+pub fn backup(path: &Path) -> anyhow::Result<()> {
+    let _ = BACKUP.set(path.to_path_buf());
+    if path.exists() {
+        log::warn!(
+            "keeping the desktop layout an earlier run saved in {}",
+            path.display()
+        );
+        return Ok(());
+    }
+
+    let layout = read().context("Failed to read the desktop layout")?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&layout)?)
+        .with_context(|| format!("Failed to save the desktop layout to {}", path.display()))?;
+    log::info!(
+        "Saved the positions of {} desktop icons to {}",
+        layout.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Puts the icons back where `backup` found them, then deletes the backup.
+/// Icons that were added since stay where they are.
+pub fn restore() -> anyhow::Result<()> {
+    let Some(path) = BACKUP.get() else {
+        return Ok(());
+    };
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let layout: Layout = serde_json::from_str(&text)
+        .with_context(|| format!("Invalid desktop layout {}", path.display()))?;
+    write(&layout).context("Failed to restore the desktop layout")?;
+    std::fs::remove_file(path)?;
+    Ok(())
+}
+
+/// The desktop's view, the way Explorer itself positions icons. See
+/// "Manipulating the positions of desktop icons" on The Old New Thing.
+unsafe fn desktop_view() -> windows::core::Result<IFolderView> {
+    // this can run on the console handler's thread, which has no COM yet
+    let _ = CoInitializeEx(None, COINIT_APARTMENTTHREADED);
+    let windows: IShellWindows = CoCreateInstance(&ShellWindows, None, CLSCTX_ALL)?;
+
+    let mut location: VARIANT = std::mem::zeroed();
+    let inner = &mut *location.Anonymous.Anonymous;
+    inner.vt = VT_I4;
+    inner.Anonymous.lVal = CSIDL_DESKTOP as i32;
+    let mut hwnd = 0;
+    let mut dispatch = None;
+    windows.FindWindowSW(
+        &location,
+        &location,
+        SWC_DESKTOP.0,
+        &mut hwnd,
+        SWFO_NEEDDISPATCH.0,
+        &mut dispatch,
+    )?;
+    let provider: IServiceProvider = dispatch
+        .ok_or_else(|| windows::core::Error::from(E_FAIL))?
+        .cast()?;
+
+    let mut browser = std::ptr::null_mut();
+    provider.QueryService(&SID_STopLevelBrowser, &IShellBrowser::IID, &mut browser)?;
+    let browser = IShellBrowser::from_raw(browser);
+    browser.QueryActiveShellView()?.cast()
+}
+
+/// How many icons fit across and down the primary monitor's work area, the
+/// screen less the taskbar, and the pixels from one icon to the next.
+pub fn grid() -> anyhow::Result<(u32, u32, [f64; 2])> {
+    let mut area = RECT::default();
+    let mut spacing = POINT::default();
+    unsafe {
+        SystemParametersInfoW(
+            SPI_GETWORKAREA,
+            0,
+            Some(&mut area as *mut RECT as *mut _),
+            SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0),
+        )
+        .ok()
+        .context("Failed to get the screen size")?;
+        desktop_view()?.GetSpacing(&mut spacing)?;
+    }
+    anyhow::ensure!(
+        spacing.x > 0 && spacing.y > 0,
+        "the desktop has no icon spacing"
+    );
+    let across = (area.right - area.left) / spacing.x;
+    let down = (area.bottom - area.top) / spacing.y;
+    Ok((
+        across as u32,
+        down as u32,
+        [spacing.x as f64, spacing.y as f64],
+    ))
+}
+
+/// File name of the desktop `item`.
+unsafe fn name(folder: &IShellFolder, item: *const ITEMIDLIST) -> anyhow::Result<String> {
+    let flags = SHGDNF(SHGDN_INFOLDER.0 | SHGDN_FORPARSING.0);
+    let mut strret = folder.GetDisplayNameOf(item, flags)?;
+    let mut text = PWSTR::null();
+    StrRetToStrW(&mut strret, Some(item), &mut text)?;
+    let name = text.to_string();
+    CoTaskMemFree(Some(text.0 as *const _));
+    Ok(name?)
+}
+
+/// Calls `f` with the view, the folder and every item on the desktop.
+fn each_item(
+    mut f: impl FnMut(&IFolderView, &IShellFolder, *const ITEMIDLIST),
+) -> anyhow::Result<()> {
+    unsafe {
+        let view = desktop_view()?;
+        let folder: IShellFolder = view.GetFolder()?;
+        for i in 0..view.ItemCount(SVGIO_ALLVIEW.0 as u32)? {
+            let item = view.Item(i)?;
+            f(&view, &folder, item);
+            CoTaskMemFree(Some(item as *const _));
+        }
+    }
+    Ok(())
+}
+
+/// Where every icon of the user's is, leaving out the game's.
+fn read() -> anyhow::Result<Layout> {
+    let mut layout = Layout::new();
+    each_item(|view, folder, item| unsafe {
+        if let (Ok(name), Ok(at)) = (name(folder, item), view.GetItemPosition(item)) {
+            if !name.starts_with("ds_") {
+                layout.insert(name, (at.x, at.y));
+            }
+        }
+    })?;
+    Ok(layout)
+}
+
+fn write(layout: &Layout) -> anyhow::Result<()> {
+    each_item(|view, folder, item| unsafe {
+        let Some(&(x, y)) = name(folder, item).ok().and_then(|n| layout.get(&n)) else {
+            return;
+        };
+        let at = POINT { x, y };
+        // one icon that won't move doesn't stop the others
+        let _ = view.SelectAndPositionItems(1, &item, Some(&at), SVSI_POSITIONITEM.0 as u32);
+    })
+}
@@ -0,0 +1,352 @@
+use crate::game::{self, SnakeDir};
+use anyhow::Context;
+use rand::seq::SliceRandom;
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+// width of the passages in generated mazes
+const CORRIDOR: usize = 2;
+// maze walls only run along multiples of this, doors sit between them
+const LATTICE: usize = CORRIDOR + 1;
+// chambers smaller than this in both directions are left open
+const MIN_CHAMBER: usize = 2 * LATTICE + 1;
+
+/// Obstacles and starting positions for a game.
+#[derive(Clone)]
+pub struct Level {
+    pub walls: HashSet<(usize, usize)>,
+    pub start: (usize, usize),
+    pub food: Vec<(usize, usize)>,
+    /// Each portal cell and the cell it leads to, both ways.
+    pub portals: HashMap<(usize, usize), (usize, usize)>,
+}
+
+impl Level {
+    /// The classic empty board.
+    pub fn empty() -> Level {
+        Level {
+            walls: HashSet::new(),
+            start: (1, 1),
+            food: vec![(2, 1)],
+            portals: HashMap::new(),
+        }
+    }
+}
+
+/// Loads a level file, checking it covers exactly the `field` (width, height).
+pub fn load(path: &Path, field: (usize, usize)) -> anyhow::Result<Level> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read level {}", path.display()))?;
+    parse(&text, field).with_context(|| format!("Invalid level {}", path.display()))
+}
+
+/// Loads every file in `dir` as a level, in filename order.
+pub fn load_dir(dir: &Path, field: (usize, usize)) -> anyhow::Result<Vec<Level>> {
+    let mut paths = Vec::new();
+    for entry in std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read levels from {}", dir.display()))?
+    {
+        let path = entry?.path();
+        if path.is_file() {
+            paths.push(path);
+        }
+    }
+    anyhow::ensure!(!paths.is_empty(), "No levels in {}", dir.display());
+    paths.sort();
+    paths.iter().map(|path| load(path, field)).collect()
+}
+
+/// One line per row: `#` is a wall, `.` is empty, `S` is where the snake
+/// starts and `F` is an initial food. A digit is one end of a portal, and
+/// the same digit must appear once more for the other end.
+pub fn parse(text: &str, field: (usize, usize)) -> anyhow::Result<Level> {
+    let (width, height) = field;
+    let mut walls = HashSet::new();
+    let mut start = None;
+    let mut food = Vec::new();
+    let mut ends: HashMap<char, Vec<(usize, usize)>> = HashMap::new();
+
+    let rows: Vec<_> = text.lines().filter(|l| !l.trim().is_empty()).collect();
+    anyhow::ensure!(
+        rows.len() == height,
+        "expected {} rows to match the playfield, found {}",
+        height,
+        rows.len()
+    );
+
+    for (y, row) in rows.iter().enumerate() {
+        let row = row.trim_end();
+        anyhow::ensure!(
+            row.chars().count() == width,
+            "line {}: expected {} columns, found {}",
+            y + 1,
+            width,
+            row.chars().count()
+        );
+
+        for (x, c) in row.chars().enumerate() {
+            match c {
+                '#' => {
+                    walls.insert((x, y));
+                }
+                '.' => {}
+                'S' => {
+                    anyhow::ensure!(
+                        start.is_none(),
+                        "line {}, column {}: second snake start",
+                        y + 1,
+                        x + 1
+                    );
+                    start = Some((x, y));
+                }
+                'F' => food.push((x, y)),
+                '0'..='9' => ends.entry(c).or_default().push((x, y)),
+                _ => anyhow::bail!("line {}, column {}: unexpected {:?}", y + 1, x + 1, c),
+            }
+        }
+    }
+
+    let start = start.unwrap_or((1, 1));
+    anyhow::ensure!(
+        !walls.contains(&start),
+        "no S, and the default start at line 2, column 2 is a wall"
+    );
+
+    let mut portals = HashMap::new();
+    for (label, cells) in ends {
+        let &[a, b] = &cells[..] else {
+            let (x, y) = cells[0];
+            anyhow::bail!(
+                "line {}, column {}: portal {} needs exactly two ends, found {}",
+                y + 1,
+                x + 1,
+                label,
+                cells.len()
+            );
+        };
+        portals.insert(a, b);
+        portals.insert(b, a);
+    }
+    anyhow::ensure!(
+        !portals.contains_key(&start),
+        "the snake can't start on a portal"
+    );
+
+    Ok(Level {
+        walls,
+        start,
+        food,
+        portals,
+    })
+}
+
+/// A random maze covering the `field` (width, height), built by recursive
+/// division. Every open cell can reach every other one, so wherever the
+/// food spawns the snake can get to it. The start leaves room for a snake
+/// of `len` cells heading `dir`.
+pub fn maze(field: (usize, usize), dir: SnakeDir, len: usize, rng: &mut impl Rng) -> Level {
+    let (width, height) = field;
+    let mut walls = HashSet::new();
+    divide(&mut walls, (0, 0), field, rng);
+
+    let cells: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .collect();
+    let fits = |start| game::body_from(start, dir, len, field).len() == len;
+    // the start and the cells ahead of it, to give the snake some room to
+    // react
+    let room = |start| {
+        game::body_from(start, dir.opposite(), 3, field)
+            .into_iter()
+            .take_while(|c| !walls.contains(c))
+            .count()
+    };
+    let starts: Vec<_> = cells
+        .iter()
+        .copied()
+        .filter(|&c| {
+            fits(c)
+                && game::body_from(c, dir, len, field)
+                    .iter()
+                    .all(|c| !walls.contains(c))
+        })
+        .collect();
+    let best = starts.iter().map(|&c| room(c)).max().unwrap_or(0);
+    let starts: Vec<_> = starts.into_iter().filter(|&c| room(c) == best).collect();
+    let start = match starts.choose(rng) {
+        Some(&start) => start,
+        None => {
+            // no straight run is long enough, so one is cut through the
+            // walls for the body and the cell ahead, which only opens the
+            // maze up more
+            let starts: Vec<_> = cells.into_iter().filter(|&c| fits(c)).collect();
+            let start = *starts.choose(rng).unwrap_or(&(0, 0));
+            let ahead = game::body_from(start, dir.opposite(), 2, field);
+            for cell in game::body_from(start, dir, len, field)
+                .into_iter()
+                .chain(ahead)
+            {
+                walls.remove(&cell);
+            }
+            start
+        }
+    };
+
+    Level {
+        walls,
+        start,
+        food: Vec::new(),
+        portals: HashMap::new(),
+    }
+}
+
+/// Adds `count` walls at random free cells of the `field`, away from `keep`.
+/// Walls that would cut off part of what the snake can reach from its start
+/// are skipped, so the food stays reachable wherever it spawns.
+pub fn scatter(
+    level: &mut Level,
+    count: usize,
+    keep: &[(usize, usize)],
+    field: (usize, usize),
+    rng: &mut impl Rng,
+) -> anyhow::Result<()> {
+    let (width, height) = field;
+    let mut free: Vec<_> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|c| {
+            !level.walls.contains(c)
+                && !level.portals.contains_key(c)
+                && !level.food.contains(c)
+                && !keep.contains(c)
+        })
+        .collect();
+    free.shuffle(rng);
+
+    let mut placed = 0;
+    let mut open = reachable(&level.walls, level.start, field);
+    for cell in free {
+        if placed == count {
+            break;
+        }
+        level.walls.insert(cell);
+        let now = reachable(&level.walls, level.start, field);
+        // only the new wall itself may go missing
+        if now.len() + usize::from(open.contains(&cell)) == open.len() {
+            open = now;
+            placed += 1;
+        } else {
+            level.walls.remove(&cell);
+        }
+    }
+    anyhow::ensure!(
+        placed == count,
+        "only room for {} of the {} obstacles",
+        placed,
+        count
+    );
+    Ok(())
+}
+
+/// Cells that can be walked to from `from` without going over the edges.
+fn reachable(
+    walls: &HashSet<(usize, usize)>,
+    from: (usize, usize),
+    field: (usize, usize),
+) -> HashSet<(usize, usize)> {
+    let (width, height) = field;
+    let mut seen = HashSet::from([from]);
+    let mut todo = vec![from];
+    while let Some((x, y)) = todo.pop() {
+        let around = [
+            (x.wrapping_sub(1), y),
+            (x + 1, y),
+            (x, y.wrapping_sub(1)),
+            (x, y + 1),
+        ];
+        for next in around {
+            if next.0 < width && next.1 < height && !walls.contains(&next) && seen.insert(next) {
+                todo.push(next);
+            }
+        }
+    }
+    seen
+}
+
+/// Splits the chamber from `min` to `max` (exclusive) with a wall that has
+/// a door in it, then does the same to both halves. Walls only go on
+/// multiples of `LATTICE` and doors only between them, so a later wall can
+/// never close an earlier door.
+fn divide(
+    walls: &mut HashSet<(usize, usize)>,
+    min: (usize, usize),
+    max: (usize, usize),
+    rng: &mut impl Rng,
+) {
+    let (x0, y0) = min;
+    let (x1, y1) = max;
+    let (w, h) = (x1 - x0, y1 - y0);
+    if w.max(h) < MIN_CHAMBER {
+        return;
+    }
+
+    let vertical = w > h || (w == h && rng.gen());
+    let ((lo, hi), (span_lo, span_hi)) = if vertical {
+        ((x0, x1), (y0, y1))
+    } else {
+        ((y0, y1), (x0, x1))
+    };
+
+    // leave at least one cell on either side of the wall
+    let lines: Vec<_> = (lo + 1..hi - 1).filter(|v| v % LATTICE == 0).collect();
+    let doors: Vec<_> = (span_lo..span_hi).filter(|v| v % LATTICE == 1).collect();
+    let (Some(&line), Some(&door)) = (lines.choose(rng), doors.choose(rng)) else {
+        return;
+    };
+
+    for v in span_lo..span_hi {
+        if !(door..door + CORRIDOR).contains(&v) {
+            walls.insert(if vertical { (line, v) } else { (v, line) });
+        }
+    }
+
+    if vertical {
+        divide(walls, (x0, y0), (line, y1), rng);
+        divide(walls, (line + 1, y0), (x1, y1), rng);
+    } else {
+        divide(walls, (x0, y0), (x1, line), rng);
+        divide(walls, (x0, line + 1), (x1, y1), rng);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn maze_start_fits_the_snake() {
+        let field = (20, 12);
+        let dirs = [
+            SnakeDir::Up,
+            SnakeDir::Down,
+            SnakeDir::Left,
+            SnakeDir::Right,
+        ];
+        for seed in 0..200 {
+            for dir in dirs {
+                let level = maze(field, dir, 3, &mut StdRng::seed_from_u64(seed));
+                let body = game::body_from(level.start, dir, 3, field);
+                let ahead = game::body_from(level.start, dir.opposite(), 2, field);
+                assert_eq!(body.len(), 3, "seed {} heading {:?}", seed, dir);
+                assert!(
+                    body.iter().chain(&ahead).all(|c| !level.walls.contains(c)),
+                    "seed {} heading {:?}",
+                    seed,
+                    dir
+                );
+            }
+        }
+    }
+}
@@ -1,171 +1,1994 @@
 use anyhow::Context;
+use autopilot::Skill;
 use bmp::Image;
+use clap::Parser;
+use color::{Color, Palette};
 use config_file::FromConfigFile;
+use difficulty::Difficulty;
 use directories::UserDirs;
+use game::{Cell, Collision, FoodKind, GameState, Mode, Rules, SnakeDir, Steering};
+use input::{Controls, Flags, Keys};
+use level::Level;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
 use rdev::{listen, Event, EventType};
-use serde::Deserialize;
+use replay::{Player, Recorder};
+use scoreboard::Scoreboard;
+use serde::{Deserialize, Serialize};
+use stats::Tracker;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Instant;
 
-#[derive(Deserialize)]
+mod achievements;
+mod autopilot;
+mod cli;
+mod color;
+mod difficulty;
+mod font;
+mod game;
+mod gamepad;
+mod highscore;
+mod input;
+#[cfg(windows)]
+mod layout;
+mod level;
+mod mirror;
+mod replay;
+mod scoreboard;
+mod shutdown;
+mod stats;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Config {
     width: u32,
     height: u32,
     offset: u32,
+    /// Lift the `MAX_SIZE` limit on width and height.
+    #[serde(default)]
+    allow_large: bool,
+    /// Work out `width`, `height` and `icon_spacing` from the desktop of
+    /// the primary monitor, so the grid fills it below the `offset` icons.
+    /// Where that can't be found out, like off Windows, the configured ones
+    /// are used.
+    #[serde(default)]
+    auto_size: bool,
+    /// "easy", "normal" or "hard" pick the speed, growth, edges, poison and
+    /// obstacles for you; setting any of those yourself still wins.
+    /// "custom" leaves them all to the settings below.
+    #[serde(default)]
+    difficulty: Difficulty,
+    /// Time between two moves, in milliseconds. Explorer needs a few hundred
+    /// ms to pick up the new icons, so anything below ~200 just burns CPU.
+    /// This is the starting speed when the game speeds up.
+    #[serde(default = "default_tick", alias = "speed_max_ms")]
+    tick_ms: u64,
+    /// Milliseconds taken off the tick for every food eaten (or point
+    /// scored, see `speed_up`), 0 keeps the speed constant.
+    #[serde(default)]
+    speed_step_ms: u64,
+    /// Speeding up never takes the tick below this, nor below
+    /// `SAFE_TICK_MS`.
+    #[serde(default = "default_speed_min", alias = "min_tick_ms")]
+    speed_min_ms: u64,
+    /// What speeds the game up: "food" counts foods eaten, "score" counts
+    /// points, so bonus food speeds it up more.
+    #[serde(default)]
+    speed_up: SpeedUp,
+    /// Time between two moves while the boost key is held, half the usual
+    /// one if unset. Never slower than without it.
+    boost_ms: Option<u64>,
+    /// Seconds counted down on the board before the snake starts moving,
+    /// at the start and after a restart. 0 starts right away.
+    #[serde(default = "default_countdown_secs")]
+    countdown_secs: u32,
+    /// Rows reserved at the top of the grid for the score. The digits are 5
+    /// cells tall, so 6 gives them a blank separator row; 0 disables it.
+    #[serde(default)]
+    score_rows: u32,
+    /// Reserve a single row instead, with one digit icon per cell.
+    #[serde(default)]
+    score_row: bool,
+    /// What happens at the edges of the playfield: "wrap" teleports to the
+    /// opposite side, "wall" frames the playfield with a deadly border.
+    /// Older configs' `wrap_edges = false` still means "wall".
+    #[serde(default)]
+    edge_behavior: EdgeBehavior,
+    /// With "wrap", whether the left and right edges wrap around. An edge
+    /// that doesn't gets a border like in "wall" mode.
+    #[serde(default = "default_wrap")]
+    wrap_x: bool,
+    /// Same for the top and bottom edges.
+    #[serde(default = "default_wrap")]
+    wrap_y: bool,
+    /// How many food items are on the board at once.
+    #[serde(default = "default_food_count")]
+    food_count: usize,
+    /// Segments gained per food, spread over the following ticks. 0 keeps
+    /// the snake at its starting length.
+    #[serde(default = "default_growth", alias = "grow_by")]
+    growth_per_food: usize,
+    /// `[[food_kinds]]`, foods of their own color and worth, spawned at
+    /// random by weight. Without any, all food is the `food` color.
+    #[serde(default)]
+    food_kinds: Vec<FoodEntry>,
+    /// Which keys steer the snake: "arrows", "wasd", "hjkl", "both" (arrows
+    /// and WASD) or "all". "mouse" turns it towards the cursor instead, see
+    /// `icon_spacing`.
+    #[serde(default, alias = "control")]
+    controls: Controls,
+    /// Pixels from one desktop icon to the next, across then down, and
+    /// where the top left icon's corner is on screen. Mouse controls need
+    /// them to tell where the snake is, with a desktop exactly `width` icons
+    /// wide and the `offset` icons before the grid.
+    #[serde(default = "default_icon_spacing")]
+    icon_spacing: [f64; 2],
+    #[serde(default)]
+    icon_origin: [f64; 2],
+    /// 2 adds a second snake steered with `keys.player2`. The game ends
+    /// as soon as one of them dies.
+    #[serde(default = "default_players")]
+    players: usize,
+    /// Crashes a snake can take before the game is over. It starts over
+    /// from the start cell at its first length, keeping its score. With a
+    /// score strip, the first player's lives show in its top right corner.
+    #[serde(default = "default_lives")]
+    lives: u32,
+    /// Where the first snake's head starts, instead of the level's `S`
+    /// (or the second cell of the second row).
+    start_x: Option<usize>,
+    start_y: Option<usize>,
+    /// Cells of the snake at the start, trailing behind the head.
+    #[serde(default = "default_start_length")]
+    start_length: usize,
+    /// "up", "down", "left" or "right".
+    #[serde(default = "default_start_dir", alias = "start_direction")]
+    start_dir: SnakeDir,
+    /// Adds a snake played by the computer, racing the players to the food.
+    /// The game ends when any snake crashes, like with two players.
+    #[serde(default)]
+    opponent: bool,
+    /// "smart" finds its way around everything, "greedy" heads straight
+    /// for the food and is easier to trap.
+    #[serde(default)]
+    opponent_skill: Skill,
+    /// The opponent only changes its mind every this many ticks, higher is
+    /// easier.
+    #[serde(default = "default_reaction_ticks")]
+    opponent_reaction_ticks: u32,
+    /// Turns remembered between two ticks, so quick presses all count.
+    #[serde(default = "default_turn_buffer")]
+    turn_buffer: usize,
+    /// Key bindings, see `input::Keys`.
+    #[serde(default)]
+    keys: Keys,
+    /// Also steer with a game controller's D-pad or left stick.
+    #[serde(default)]
+    gamepad: bool,
+    /// Let the game play itself until a direction key is pressed.
+    #[serde(default)]
+    autopilot: bool,
+    /// Let the game play itself for good, direction keys do nothing.
+    #[serde(default)]
+    autoplay: bool,
+    /// Spawn a bonus food every this many foods eaten, 0 to disable.
+    #[serde(default)]
+    bonus_interval: u32,
+    /// Ticks a bonus food stays on the board before vanishing.
+    #[serde(default = "default_bonus_ticks")]
+    bonus_ticks: u32,
+    /// Score awarded for eating a bonus food.
+    #[serde(default = "default_bonus_score")]
+    bonus_score: u32,
+    /// Probability (0 to 1) that a poison cell appears each time food respawns.
+    #[serde(default)]
+    poison_chance: f64,
+    /// Tail segments lost when eating poison. Dropping below one segment
+    /// ends the game.
+    #[serde(default = "default_poison_penalty")]
+    poison_penalty: usize,
+    /// Chance (0 to 1) that eating food spawns a ghost power-up, which lets
+    /// the snake go through itself for `ghost_ticks` moves.
+    #[serde(default)]
+    ghost_chance: f64,
+    #[serde(default = "default_ghost_ticks")]
+    ghost_ticks: u32,
+    /// Chance (0 to 1) that eating food spawns a confusion food, which
+    /// reverses the controls for `confusion_ticks` moves.
+    #[serde(default)]
+    confusion_chance: f64,
+    #[serde(default = "default_confusion_ticks")]
+    confusion_ticks: u32,
+    /// Chance (0 to 1) that eating food spawns a slow motion power-up,
+    /// which makes every move take `slow_factor` times as long for
+    /// `slow_ticks` moves.
+    #[serde(default)]
+    slow_chance: f64,
+    #[serde(default = "default_slow_ticks")]
+    slow_ticks: u32,
+    #[serde(default = "default_slow_factor")]
+    slow_factor: f64,
+    /// Chance (0 to 1) that eating food spawns a shrink power-up, which
+    /// cuts `shrink_segments` off the tail. The head always stays.
+    #[serde(default)]
+    shrink_chance: f64,
+    #[serde(default = "default_shrink_segments")]
+    shrink_segments: usize,
+    /// Width and height of the icon images, in pixels, a power of two
+    /// between 16 and 1024. Explorer scales them to the icon size anyway,
+    /// so this only changes how sharp the digits look and how much gets
+    /// written every frame: a 256 icon is 192 KiB.
+    #[serde(default = "default_pixel_size")]
+    pixel_size: u32,
+    /// "bmp", or "png" for much smaller files. Explorer shows both.
+    #[serde(default)]
+    image_format: IconFormat,
+    /// `[colors]`, each either `[r, g, b]` or `"#RRGGBB"`, see
+    /// `color::Palette`.
+    #[serde(default)]
+    colors: Palette,
+    /// Shades the bodies from their color behind the head to dark at the
+    /// tail, in `GRADIENT_BANDS` steps. Replaces `snake_sprite`.
+    #[serde(default)]
+    gradient: bool,
+    /// Pictures drawn instead of the plain colors, scaled to `pixel_size`.
+    /// The head and body ones are for the first player. Most formats work:
+    /// PNG, JPEG, BMP, GIF.
+    head_sprite: Option<PathBuf>,
+    snake_sprite: Option<PathBuf>,
+    food_sprite: Option<PathBuf>,
+    background_sprite: Option<PathBuf>,
+    /// Text file describing obstacles inside the playfield, see `level::parse`.
+    /// It must have exactly as many rows and columns as the playfield.
+    level: Option<PathBuf>,
+    /// Folder to put the icons in instead of Desktop\snake.
+    output_dir: Option<PathBuf>,
+    /// Folder of level files played one after the other, in filename order.
+    levels_dir: Option<PathBuf>,
+    /// Foods to eat before moving on to the next level of `levels_dir`.
+    #[serde(default = "default_foods_per_level")]
+    foods_per_level: u32,
+    /// Milliseconds taken off the tick with every new level.
+    #[serde(default = "default_level_step")]
+    level_step_ms: u64,
+    /// What happens after the last level: "win" ends the game, "loop"
+    /// starts over from the first one, still speeding up.
+    #[serde(default)]
+    after_last_level: AfterLastLevel,
+    /// "classic", "survival" to win by staying alive for `survival_secs`
+    /// while an obstacle appears every `obstacle_secs`, or "tron" where there's
+    /// no food and the trail stays, one point per move survived.
+    #[serde(default)]
+    mode: Mode,
+    #[serde(default = "default_survival_secs")]
+    survival_secs: u64,
+    #[serde(default = "default_obstacle_secs")]
+    obstacle_secs: u64,
+    /// Ticks between two walls appearing at random during the game, in any
+    /// mode, 0 for none. They stay until the game is over.
+    #[serde(default)]
+    obstacle_interval: u32,
+    /// Walls added during a game stop at this many, survival mode's
+    /// included. 0 doesn't limit them.
+    #[serde(default)]
+    max_obstacles: usize,
+    /// Ticks between two rings of the playfield turning into walls, from the
+    /// outside in, 0 to keep it whole. Whatever is in a ring is lost, the
+    /// snake included.
+    #[serde(default)]
+    shrink_interval: u32,
+    /// Cells the playfield keeps at least, once it's shrunk that far.
+    #[serde(default = "default_shrink_min_area")]
+    shrink_min_area: usize,
+    /// Ticks before uneaten food moves elsewhere, blinking for the last few.
+    /// 0 leaves it in place.
+    #[serde(default)]
+    food_ttl_ticks: u32,
+    /// Food eaten within this many ticks of the previous one is worth one
+    /// more point than it, up to `game::MAX_COMBO`. 0 turns combos off. With
+    /// a score strip, its top right cell shows the multiplier, from yellow
+    /// to red, and goes dark when the chain breaks.
+    #[serde(default)]
+    combo_ticks: u32,
+    /// Generate a random maze instead of loading a level.
+    #[serde(default)]
+    maze: bool,
+    /// Random walls added to the level, never on the starting snake or
+    /// right in front of it.
+    #[serde(default)]
+    obstacles: usize,
+    /// Fixes the random number generator, so the same seed and the same key
+    /// presses play out the same game (maze included).
+    seed: Option<u64>,
+    /// Saves every run to a file in `replays`, with the seed, the settings
+    /// and each move.
+    #[serde(default)]
+    record: bool,
+    /// File each game's stats are added to, one JSON object per line. The
+    /// default is `stats.jsonl` in the user's data folder.
+    stats_path: Option<PathBuf>,
+    /// What walls from the level do: "fatal" ends the game, "solid" just
+    /// stops the snake until it turns.
+    #[serde(default)]
+    wall_collision: WallCollision,
+    /// Practice without dying: running into anything stops the snake until
+    /// it turns, or lets it through with `pass_through`. Scores aren't
+    /// saved as records.
+    #[serde(default)]
+    invincible: bool,
+    #[serde(default)]
+    pass_through: bool,
+    /// Whatever keys the rest doesn't know about, which includes settings
+    /// older configs have under names that changed since. `upgrade` moves
+    /// those where they belong, the rest is ignored.
+    #[serde(flatten, skip_serializing)]
+    old_keys: BTreeMap<String, serde_json::Value>,
+}
+
+/// A kind of food from `[[food_kinds]]`.
+#[derive(Serialize, Deserialize, Clone)]
+struct FoodEntry {
+    color: Color,
+    /// Segments gained, `growth_per_food` if unset.
+    growth: Option<usize>,
+    /// Points before the combo multiplier.
+    #[serde(default = "default_food_score")]
+    score: u32,
+    /// How often it spawns compared to the others.
+    #[serde(default = "default_food_weight")]
+    weight: u32,
+}
+
+fn default_food_score() -> u32 {
+    1
+}
+
+fn default_food_weight() -> u32 {
+    1
+}
+
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum IconFormat {
+    #[default]
+    Bmp,
+    Png,
+}
+
+impl IconFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            IconFormat::Bmp => "bmp",
+            IconFormat::Png => "png",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum WallCollision {
+    #[default]
+    Fatal,
+    Solid,
+}
+
+fn default_pixel_size() -> u32 {
+    256
+}
+
+fn default_poison_penalty() -> usize {
+    2
+}
+
+fn default_ghost_ticks() -> u32 {
+    20
+}
+
+fn default_confusion_ticks() -> u32 {
+    15
+}
+
+fn default_slow_ticks() -> u32 {
+    30
+}
+
+fn default_slow_factor() -> f64 {
+    2.0
+}
+
+fn default_shrink_segments() -> usize {
+    3
+}
+
+fn default_bonus_ticks() -> u32 {
+    15
+}
+
+fn default_bonus_score() -> u32 {
+    5
+}
+
+fn default_icon_spacing() -> [f64; 2] {
+    [75.0, 100.0]
+}
+
+fn default_wrap() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum EdgeBehavior {
+    #[default]
+    Wrap,
+    Wall,
+}
+
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum AfterLastLevel {
+    #[default]
+    Win,
+    Loop,
 }
 
-const PIXEL_SIZE: u32 = 256;
+fn default_survival_secs() -> u64 {
+    60
+}
+
+fn default_obstacle_secs() -> u64 {
+    5
+}
+
+fn default_shrink_min_area() -> usize {
+    16
+}
+
+fn default_foods_per_level() -> u32 {
+    10
+}
+
+fn default_level_step() -> u64 {
+    50
+}
+
+#[derive(Serialize, Deserialize, Default, Copy, Clone, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum SpeedUp {
+    #[default]
+    Food,
+    Score,
+}
+
+fn default_start_length() -> usize {
+    1
+}
+
+fn default_start_dir() -> SnakeDir {
+    SnakeDir::Right
+}
+
+fn default_reaction_ticks() -> u32 {
+    1
+}
+
+fn default_lives() -> u32 {
+    1
+}
+
+fn default_turn_buffer() -> usize {
+    3
+}
+
+fn default_players() -> usize {
+    1
+}
+
+fn default_food_count() -> usize {
+    1
+}
+
+fn default_growth() -> usize {
+    1
+}
+
+fn default_countdown_secs() -> u32 {
+    3
+}
+
+fn default_tick() -> u64 {
+    1200
+}
+
+fn default_speed_min() -> u64 {
+    200
+}
+
+// written out for new users, keep it in sync with Config::default
+const DEFAULT_CONFIG: &str = include_str!("default_config.toml");
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            width: 16,
+            height: 8,
+            offset: 0,
+            allow_large: false,
+            auto_size: false,
+            difficulty: Difficulty::default(),
+            tick_ms: default_tick(),
+            speed_step_ms: 0,
+            speed_min_ms: default_speed_min(),
+            speed_up: SpeedUp::default(),
+            boost_ms: None,
+            countdown_secs: default_countdown_secs(),
+            score_rows: 0,
+            score_row: false,
+            edge_behavior: EdgeBehavior::default(),
+            wrap_x: true,
+            wrap_y: true,
+            food_count: default_food_count(),
+            growth_per_food: default_growth(),
+            food_kinds: Vec::new(),
+            controls: Controls::default(),
+            icon_spacing: default_icon_spacing(),
+            icon_origin: [0.0, 0.0],
+            players: default_players(),
+            lives: default_lives(),
+            opponent: false,
+            opponent_skill: Skill::default(),
+            opponent_reaction_ticks: default_reaction_ticks(),
+            start_x: None,
+            start_y: None,
+            start_length: default_start_length(),
+            start_dir: default_start_dir(),
+            turn_buffer: default_turn_buffer(),
+            keys: Keys::default(),
+            gamepad: false,
+            autopilot: false,
+            autoplay: false,
+            bonus_interval: 0,
+            bonus_ticks: default_bonus_ticks(),
+            bonus_score: default_bonus_score(),
+            poison_chance: 0.0,
+            poison_penalty: default_poison_penalty(),
+            pixel_size: default_pixel_size(),
+            image_format: IconFormat::default(),
+            ghost_chance: 0.0,
+            ghost_ticks: default_ghost_ticks(),
+            confusion_chance: 0.0,
+            confusion_ticks: default_confusion_ticks(),
+            slow_chance: 0.0,
+            slow_ticks: default_slow_ticks(),
+            slow_factor: default_slow_factor(),
+            shrink_chance: 0.0,
+            shrink_segments: default_shrink_segments(),
+            colors: Palette::default(),
+            gradient: false,
+            head_sprite: None,
+            snake_sprite: None,
+            food_sprite: None,
+            background_sprite: None,
+            level: None,
+            output_dir: None,
+            levels_dir: None,
+            foods_per_level: default_foods_per_level(),
+            level_step_ms: default_level_step(),
+            after_last_level: AfterLastLevel::default(),
+            food_ttl_ticks: 0,
+            combo_ticks: 0,
+            shrink_interval: 0,
+            shrink_min_area: default_shrink_min_area(),
+            mode: Mode::default(),
+            survival_secs: default_survival_secs(),
+            obstacle_secs: default_obstacle_secs(),
+            obstacle_interval: 0,
+            max_obstacles: 0,
+            maze: false,
+            obstacles: 0,
+            seed: None,
+            record: false,
+            stats_path: None,
+            wall_collision: WallCollision::default(),
+            invincible: false,
+            pass_through: false,
+            old_keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// Largest width or height without `allow_large`, every cell is a file
+/// Explorer has to keep up with.
+const MAX_SIZE: u32 = 64;
+
+/// Speeding up stops here, Explorer can't redraw the icons much faster.
+const SAFE_TICK_MS: u64 = 100;
 
-fn refresh_destop() {
+// tried in order until one fits on the grid
+const GAME_OVER_TEXT: &[&[&str]] = &[&["GAME OVER"], &["GAME", "OVER"], &["DEAD"], &["X"]];
+const WIN_TEXT: &[&[&str]] = &[&["YOU WIN"], &["YOU", "WIN"], &["WIN"], &["!"]];
+
+/// Breather after a new level shows up, before the snake moves.
+const LEVEL_PAUSE_MS: u64 = 2000;
+
+/// Times a crashed snake blinks before coming back, and how fast.
+const RESPAWN_FLASHES: usize = 3;
+const FLASH_MS: u64 = 300;
+/// Times the snakes flash green on a win.
+const WIN_FLASHES: usize = 3;
+/// Shades of a body with `gradient`, few enough that a move only redraws
+/// the cells at the edges between them.
+const GRADIENT_BANDS: usize = 4;
+
+/// Tells Explorer which files changed so it redraws their icons, or drops
+/// them if they were deleted.
+///
+/// This used to broadcast a single `SHCNE_ASSOCCHANGED`, which makes Explorer
+/// throw away and rebuild every icon it knows about. That's slow enough that
+/// it would fall behind and skip frames, which is why it ended up commented
+/// out. `SHCNE_UPDATEITEM` on each path only invalidates the thumbnails that
+/// actually changed, and `FLUSHNOWAIT` keeps us from blocking on Explorer.
+#[cfg(windows)]
+fn refresh_desktop(paths: &[PathBuf]) {
+    use std::os::windows::ffi::OsStrExt;
     use windows::Win32::UI::Shell::SHChangeNotify;
-    use windows::Win32::UI::Shell::SHCNE_ASSOCCHANGED;
-    use windows::Win32::UI::Shell::SHCNF_IDLIST;
+    use windows::Win32::UI::Shell::{SHCNE_DELETE, SHCNE_UPDATEITEM};
+    use windows::Win32::UI::Shell::{SHCNF_FLUSHNOWAIT, SHCNF_PATHW};
 
-    unsafe {
-        SHChangeNotify(SHCNE_ASSOCCHANGED, SHCNF_IDLIST, None, None);
+    for path in paths {
+        let wide: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        let event = if path.exists() {
+            SHCNE_UPDATEITEM
+        } else {
+            SHCNE_DELETE
+        };
+        unsafe {
+            SHChangeNotify(
+                event,
+                SHCNF_PATHW | SHCNF_FLUSHNOWAIT,
+                Some(wide.as_ptr() as *const _),
+                None,
+            );
+        }
     }
 }
 
-fn main() -> anyhow::Result<()> {
-    let config = Config::from_config_file("config.toml").context("Failed to load config")?;
+/// Nautilus and friends watch the folder with inotify, so they already see
+/// the files change; bumping the folder's modification time on top of that
+/// gets the ones that only poll the folder to reload it, thumbnails
+/// included. Best effort, a failure only means a late frame.
+#[cfg(target_os = "linux")]
+fn refresh_desktop(paths: &[PathBuf]) {
+    let dirs: std::collections::HashSet<_> = paths.iter().filter_map(|p| p.parent()).collect();
+    for dir in dirs {
+        let touched =
+            std::fs::File::open(dir).and_then(|f| f.set_modified(std::time::SystemTime::now()));
+        if let Err(error) = touched {
+            log::debug!("Failed to touch {}: {}", dir.display(), error);
+        }
+    }
+}
 
-    let desktop = get_desktop_dir()?;
+/// Finder doesn't watch the folder nearly as closely, asking it to update
+/// the folder is the only thing that gets the new icons in reasonably
+/// fast. Even then it redraws them when it feels like it, so expect some
+/// lag and skipped frames. `osascript` takes a while to start, it runs in
+/// the background to keep the game going.
+#[cfg(target_os = "macos")]
+fn refresh_desktop(paths: &[PathBuf]) {
+    let dirs: std::collections::HashSet<_> = paths.iter().filter_map(|p| p.parent()).collect();
+    for dir in dirs {
+        let script = format!(
+            "tell application \"Finder\" to update (POSIX file {:?} as alias)",
+            dir.display().to_string()
+        );
+        std::thread::spawn(move || {
+            let _ = std::process::Command::new("osascript")
+                .args(["-e", &script])
+                .status();
+        });
+    }
+}
 
-    clear_old_files(&desktop)?;
+#[cfg(not(any(windows, target_os = "linux", target_os = "macos")))]
+fn refresh_desktop(_paths: &[PathBuf]) {}
 
-    let mut black = Image::new(PIXEL_SIZE, PIXEL_SIZE);
-    for (x, y) in black.coordinates() {
-        black.set_pixel(x, y, bmp::Pixel::new(0, 0, 0));
+fn main() -> anyhow::Result<()> {
+    let args = cli::Args::parse();
+    env_logger::Builder::new()
+        .filter_level(args.log_level())
+        .parse_default_env()
+        .format(|buf, record| match record.level() {
+            log::Level::Error => writeln!(buf, "Error: {}", record.args()),
+            log::Level::Warn => writeln!(buf, "Warning: {}", record.args()),
+            _ => writeln!(buf, "{}", record.args()),
+        })
+        .init();
+    let config_path = &args.config;
+    // a broken config is an error, only a missing one gets the defaults
+    let mut config = if config_path.exists() {
+        let mut config = Config::from_config_file(config_path).context("Failed to load config")?;
+        let explicit =
+            difficulty::Explicit::from_config_file(config_path).context("Failed to load config")?;
+        difficulty::apply(&mut config, &explicit);
+        config
+    } else {
+        write_default_config(config_path)?;
+        log::info!(
+            "No {} found, wrote one with the default settings",
+            config_path.display()
+        );
+        Config::default()
+    };
+    if config.auto_size {
+        config.auto_size();
     }
-    let mut red = Image::new(PIXEL_SIZE, PIXEL_SIZE);
-    for (x, y) in red.coordinates() {
-        red.set_pixel(x, y, bmp::Pixel::new(255, 0, 0));
+    args.apply(&mut config);
+
+    let mut playback = None;
+    if let Some(path) = &args.replay {
+        anyhow::ensure!(args.replay_speed > 0.0, "replay-speed must be above 0");
+        let replay = replay::load(path)?;
+        config = replay.settings(&config)?;
+        playback = Some(Player::new(&replay)?);
     }
 
-    for o in 0..config.offset {
-        black.save(desktop.join(format!("ds_o{}.bmp", o)))?;
+    config.upgrade().context("Invalid config")?;
+    config.validate().context("Invalid config")?;
+    ICON_FORMAT.get_or_init(|| config.image_format);
+
+    let desktop = match &config.output_dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+            dir.clone()
+        }
+        None => get_desktop_dir()?,
+    };
+
+    // the real desktop gets its icons put back in place on the way out
+    #[cfg(windows)]
+    if let Some(path) = layout::default_path().filter(|_| config.output_dir.is_none()) {
+        if let Err(error) = layout::backup(&path) {
+            log::warn!("{:#}, icons will stay where the game leaves them", error);
+        }
+    }
+
+    clear_old_files(&desktop)?;
+    shutdown::install(&desktop)?;
+    // the first frame is written in one go, the cleanup waits for it
+    let drawing = shutdown::hold();
+
+    let high_score_path = desktop.join("highscore.txt");
+    let mut high_score = highscore::load_high_score(&high_score_path);
+    // practice runs and replays are shown against the record but never
+    // beat it
+    let record_path =
+        (!config.invincible && args.replay.is_none()).then_some(high_score_path.as_path());
+    let stats_path = match &args.replay {
+        Some(_) => None,
+        None => config.stats_path.clone().or_else(stats::default_path),
+    };
+    let mut tracker = Tracker::default();
+    // practice runs and replays don't unlock anything, like records
+    let mut unlocked = achievements::default_path()
+        .filter(|_| !config.invincible && args.replay.is_none())
+        .map(achievements::Unlocked::load);
+    let walls = config.wraps() == (false, false);
+    println!("High score: {}", high_score);
+
+    // every image is drawn from these, checked by `validate` already
+    let colors = config.colors.pixels()?;
+    let solid = |color| solid_image(color, config.pixel_size);
+    // a sprite if there's one, the color otherwise
+    let sprite_or = |sprite: &Option<PathBuf>, color| match sprite {
+        Some(path) => load_sprite(path, config.pixel_size),
+        None => Ok(solid(color)),
+    };
+    let black = sprite_or(&config.background_sprite, colors.background)?;
+    // each kind of food and how it looks about to move, the food sprite
+    // only goes for the plain kind
+    let food_colors = if config.food_kinds.is_empty() {
+        vec![colors.food]
+    } else {
+        config
+            .food_kinds
+            .iter()
+            .map(|kind| kind.color.pixel())
+            .collect::<anyhow::Result<_>>()?
+    };
+    let foods = food_colors
+        .iter()
+        .map(|&color| {
+            if config.food_kinds.is_empty() {
+                sprite_or(&config.food_sprite, color)
+            } else {
+                Ok(solid(color))
+            }
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let fading_foods: Vec<_> = food_colors.iter().map(|&color| solid(dim(color))).collect();
+    let dead = solid(colors.dead);
+    let text = solid(colors.text);
+    let yellow = solid(colors.timer);
+    let victory = solid(colors.victory);
+    let green = solid(colors.poison);
+    let wall = solid(colors.wall);
+    let ghost = solid(colors.ghost);
+    let bonus = solid(colors.bonus);
+    let confused = solid(colors.confusion);
+    let slow = solid(colors.slow);
+    let shrink = solid(colors.shrink);
+    let portal = solid(colors.portal);
+    // one of each per snake, the players' then the computer's
+    let opponent = config.opponent.then_some(config.players);
+    let snake_count = config.players + config.opponent as usize;
+    let mut head_colors = vec![colors.head, colors.player2_head];
+    let mut body_colors = vec![colors.snake, colors.player2_snake];
+    head_colors.truncate(config.players);
+    body_colors.truncate(config.players);
+    if config.opponent {
+        head_colors.push(colors.opponent_head);
+        body_colors.push(colors.opponent_snake);
     }
+    // a trail looks like a darker head, so the head still stands out
+    if config.mode == Mode::Tron {
+        body_colors = head_colors.iter().map(|&c| trail(c)).collect();
+    }
+    let mut heads: Vec<_> = head_colors.iter().map(|&c| solid(c)).collect();
+    let mut bodies: Vec<_> = body_colors.iter().map(|&c| solid(c)).collect();
+    heads[0] = sprite_or(&config.head_sprite, head_colors[0])?;
+    bodies[0] = sprite_or(&config.snake_sprite, body_colors[0])?;
+    // the snakes while the game is paused
+    let dim_heads: Vec<_> = head_colors.iter().map(|&c| solid(dim(c))).collect();
+    let dim_bodies: Vec<_> = body_colors.iter().map(|&c| solid(dim(c))).collect();
+    let body_bands: Vec<Vec<_>> = body_colors
+        .iter()
+        .map(|&c| (0..GRADIENT_BANDS).map(|b| solid(shade(c, b))).collect())
+        .collect();
 
+    let mut blank: Vec<_> = (0..config.offset)
+        .map(|o| desktop.join(format!("ds_o{}.{}", o, icon_format().extension())))
+        .collect();
     for y in 0..config.height {
         for x in 0..config.width {
-            black.save(Path::new(&desktop).join(format!("ds_p{}-{}.bmp", y, x)))?;
+            blank.push(cell_path(&desktop, x as usize, y as usize));
         }
     }
+    fill(&black, &blank)?;
 
-    let mut snake_bits = vec![(1, 1)];
+    // the score strip sits above the playfield, which has its own coordinates
+    let top = if config.score_row {
+        1
+    } else {
+        config.score_rows as usize
+    };
+    // edges that don't wrap are framed by a one-cell border
+    let (wrap_x, wrap_y) = config.wraps();
+    let (border_x, border_y) = (!wrap_x as usize, !wrap_y as usize);
+    let width = config.width as usize;
+    // the survival timer takes the bottom row
+    let timer_row = (config.mode == Mode::Survival).then(|| config.height as usize - 1);
+    let height = timer_row.unwrap_or(config.height as usize);
+    let origin = (border_x, top + border_y);
+    let field = (
+        width.saturating_sub(2 * border_x),
+        height.saturating_sub(top + 2 * border_y),
+    );
+    // `place_start` checks the snake fits, this is only for the borders
+    // leaving nothing at all
+    anyhow::ensure!(
+        field.0 > 0 && field.1 > 0,
+        "the playfield is too small for the snake"
+    );
 
-    #[derive(Copy, Clone)]
-    enum SnakeDir {
-        Up,
-        Down,
-        Left,
-        Right,
+    for y in top..height {
+        for x in 0..width {
+            let side = border_x > 0 && (x == 0 || x == width - 1);
+            let end = border_y > 0 && (y == top || y == height - 1);
+            if side || end {
+                wall.save(cell_path(&desktop, x, y))?;
+            }
+        }
     }
 
-    let snake_dir = Arc::new(Mutex::new(SnakeDir::Right));
+    // the combo cell, then extra lives in the top right corner of the
+    // score strip, each one cell apart from the rest
+    let combo_shown = config.combo_ticks > 0 && top > 0 && width > 2;
+    let lives_right = width - 2 * combo_shown as usize;
+    let lives_shown = config.lives > 1 && top > 0 && (config.lives as usize) < lives_right / 2;
+    let score_width = if lives_shown {
+        lives_right - config.lives as usize - 1
+    } else {
+        lives_right
+    };
+    // one icon per multiplier above 1, from combo_low to combo_high
+    let combo_icons: Vec<_> = (2..=game::MAX_COMBO)
+        .map(|level| {
+            let (low, high) = (colors.combo_low, colors.combo_high);
+            let (at, span) = (level - 2, game::MAX_COMBO - 2);
+            let mix = |a: u8, b: u8| ((a as u32 * (span - at) + b as u32 * at) / span) as u8;
+            solid(bmp::Pixel::new(
+                mix(low.r, high.r),
+                mix(low.g, high.g),
+                mix(low.b, high.b),
+            ))
+        })
+        .collect();
+    let mut combo_drawn = 1;
+    let mut scoreboard = Scoreboard::new(
+        score_width,
+        (!config.score_row).then_some(top),
+        config.pixel_size,
+        black.clone(),
+        &colors,
+    );
+    scoreboard.draw(&desktop, 0, false)?;
+
+    let steering: Vec<_> = (0..snake_count)
+        .map(|_| {
+            Arc::new(Mutex::new(Steering::new(
+                SnakeDir::Right,
+                config.turn_buffer,
+            )))
+        })
+        .collect();
+    let flags = Arc::new(Flags::default());
 
     let mut updates = Vec::new();
 
-    let mut food_pos = (2, 1);
+    // picked here rather than left to the RNG so a recording can tell it
+    let seed = config.seed.unwrap_or_else(rand::random);
+    println!("Seed: {}", seed);
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut recorder = if config.record {
+        let snapshot = Config {
+            seed: Some(seed),
+            ..config.clone()
+        };
+        Some(Recorder::new(Path::new(replay::DIR), seed, snapshot)?)
+    } else {
+        None
+    };
 
-    fn wrap(val: i32, max: i32) -> i32 {
-        if val < 0 {
-            max - 1
-        } else if val >= max {
-            0
-        } else {
-            val
-        }
+    let mut levels = match &config.levels_dir {
+        Some(dir) => level::load_dir(dir, field)?,
+        None => Vec::new(),
+    };
+    for (i, level) in levels.iter_mut().enumerate() {
+        config
+            .place_start(level, field)
+            .with_context(|| format!("Invalid start in level {}", i + 1))?;
+        config
+            .scatter_obstacles(level, field, &mut rng)
+            .with_context(|| format!("Invalid obstacles in level {}", i + 1))?;
     }
-
-    impl TryFrom<rdev::Key> for SnakeDir {
-        type Error = ();
-        fn try_from(key: rdev::Key) -> Result<Self, Self::Error> {
-            match key {
-                rdev::Key::UpArrow => Ok(SnakeDir::Up),
-                rdev::Key::DownArrow => Ok(SnakeDir::Down),
-                rdev::Key::LeftArrow => Ok(SnakeDir::Left),
-                rdev::Key::RightArrow => Ok(SnakeDir::Right),
-                _ => Err(()),
-            }
-        }
+    let mut level = match &config.level {
+        Some(path) => level::load(path, field)?,
+        None if !levels.is_empty() => levels[0].clone(),
+        None if config.maze => level::maze(field, config.start_dir, config.start_length, &mut rng),
+        None => Level::empty(),
+    };
+    config
+        .place_start(&mut level, field)
+        .context("Invalid start")?;
+    if levels.is_empty() {
+        config
+            .scatter_obstacles(&mut level, field, &mut rng)
+            .context("Invalid obstacles")?;
     }
 
-    let snake_dir_2 = snake_dir.clone();
+    let mut state = GameState::new(config.rules(), field, level, steering.clone(), rng);
+    let two_players = config.players == 2;
+    updates.extend(state.cells());
+
+    let mut message_cells = Vec::new();
+
+    let steering_2 = steering.clone();
+    let flags_2 = flags.clone();
+    let controls = config.controls;
+    let keys = config.keys;
+    let watching = playback.is_some();
+    let autoplay = config.autoplay;
     let callback = move |event: Event| {
+        if let EventType::MouseMove { x, y } = event.event_type {
+            if controls == Controls::Mouse && !watching && !autoplay {
+                flags_2.manual.store(true, Ordering::SeqCst);
+                *flags_2.cursor.lock().unwrap_or_else(|e| e.into_inner()) = Some((x, y));
+            }
+        }
+        if let EventType::KeyRelease(k) = event.event_type {
+            if k == keys.boost {
+                flags_2.boosting.store(false, Ordering::SeqCst);
+            }
+        }
         if let EventType::KeyPress(k) = event.event_type {
-            println!("Key: {:?}", k);
-            let new_dir = match *snake_dir_2.lock().unwrap() {
-                SnakeDir::Up | SnakeDir::Down => match k.try_into() {
-                    Ok(x @ (SnakeDir::Left | SnakeDir::Right)) => Some(x),
-                    _ => None,
-                },
-                SnakeDir::Left | SnakeDir::Right => match k.try_into() {
-                    Ok(x @ (SnakeDir::Up | SnakeDir::Down)) => Some(x),
-                    _ => None,
-                },
-            };
-            if let Some(new_dir) = new_dir {
-                *snake_dir_2.lock().unwrap() = new_dir;
+            log::debug!("Key: {:?}", k);
+            if k == keys.boost && !watching {
+                flags_2.boosting.store(true, Ordering::SeqCst);
+            }
+            if watching && k != keys.quit && k != keys.pause {
+                return;
+            }
+            if !flags_2.press(k, &keys) || autoplay {
+                return;
+            }
+            // the second player's keys win over the first player's
+            if let Some(dir) = keys.player2.steer(k).filter(|_| two_players) {
+                game::lock(&steering_2[1]).press(dir);
+            } else if let Some(dir) = keys.steer(k, controls) {
+                flags_2.manual.store(true, Ordering::SeqCst);
+                game::lock(&steering_2[0]).press(dir);
             }
         }
     };
 
     std::thread::spawn(move || {
         if let Err(error) = listen(callback) {
-            println!("Error: {:?}", error)
+            log::error!("Keyboard unavailable: {:?}", error)
         }
     });
 
+    // autoplay steers itself, like for the keyboard and mouse
+    if config.gamepad && !watching && !autoplay {
+        gamepad::spawn(steering[0].clone(), flags.clone());
+    }
+
+    drop(drawing);
+    log::info!("Game started on a {}x{} field", field.0, field.1);
+
+    let mut tick_ms = config.tick_ms;
+    let mut shown_paused = false;
+    let mut autopilot = config.autopilot;
+    let mut reaction_clock = 0u32;
+    let mut timer_shown = 0;
+    let mut lives_drawn = 0;
+    // levels cleared, counting every lap when they loop
+    let mut stage = 0;
+    let mut next_level_at = config.foods_per_level;
+    let mut countdown = config.countdown_secs;
+    let mut replay_over = false;
+    let speed = if playback.is_some() {
+        args.replay_speed
+    } else {
+        1.0
+    };
+    let mut countdown_cells = Vec::new();
+    // band each body cell was last drawn with, with `gradient`
+    let mut shown_bands = HashMap::new();
+
     loop {
-        let (head_x, head_y) = *snake_bits.last().unwrap();
-        let head_x = head_x as i32;
-        let head_y = head_y as i32;
-
-        let (new_x, new_y) = match *snake_dir.lock().unwrap() {
-            SnakeDir::Up => (head_x, head_y - 1),
-            SnakeDir::Down => (head_x, head_y + 1),
-            SnakeDir::Left => (head_x - 1, head_y),
-            SnakeDir::Right => (head_x + 1, head_y),
-        };
+        if flags.quit.load(Ordering::SeqCst) {
+            if !state.game_over {
+                save_if_record(record_path, state.score(), high_score)?;
+                save_stats(stats_path.as_deref(), &mut tracker, &state, seed)?;
+            }
+            if let Some(recorder) = &mut recorder {
+                recorder.save()?;
+            }
+            let _drawing = shutdown::hold();
+            shutdown::cleanup(&desktop);
+            return Ok(());
+        }
+
+        // direction changes still go through while paused, they just wait
+        // for the next tick after resuming
+        if flags.paused.load(Ordering::SeqCst) {
+            if !shown_paused && !state.game_over {
+                let _drawing = shutdown::hold();
+                let dimmed = state
+                    .cells()
+                    .into_iter()
+                    .filter_map(|(x, y, cell)| match cell {
+                        Cell::Head(i) => Some((x, y, &dim_heads[i])),
+                        Cell::Snake(i) | Cell::Ghost(i) | Cell::Confused(i) => {
+                            Some((x, y, &dim_bodies[i]))
+                        }
+                        _ => None,
+                    });
+                refresh_desktop(&draw_cells(&desktop, origin, dimmed)?);
+                shown_paused = true;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            continue;
+        }
+
+        if shown_paused {
+            // back to the normal colors with the next frame
+            updates.extend(state.cells().into_iter().filter(|&(_, _, c)| {
+                matches!(
+                    c,
+                    Cell::Head(_) | Cell::Snake(_) | Cell::Ghost(_) | Cell::Confused(_)
+                )
+            }));
+            shown_paused = false;
+        }
 
-        let new_x = wrap(new_x, config.width as i32);
-        let new_y = wrap(new_y, config.height as i32);
+        if playback.as_mut().is_some_and(Player::restart_due) {
+            flags.restart.store(true, Ordering::SeqCst);
+        }
+        let restarting = flags.restart.swap(false, Ordering::SeqCst);
+        if state.game_over && !restarting {
+            std::thread::sleep(std::time::Duration::from_millis(100));
+            continue;
+        }
 
-        let snake_new_bit = (new_x as usize, new_y as usize);
+        // overlays drawn on top of the playfield, outside of the game state
+        let mut overlay = Vec::new();
 
-        snake_bits.push(snake_new_bit);
-        updates.push((snake_new_bit.0, snake_new_bit.1, true));
+        if restarting {
+            if !state.game_over {
+                save_if_record(record_path, state.score(), high_score)?;
+                save_stats(stats_path.as_deref(), &mut tracker, &state, seed)?;
+            }
+            // the record only moves once the run is over, so the scoreboard
+            // keeps showing it as beaten until then
+            high_score = high_score.max(state.score());
 
-        if snake_new_bit != food_pos {
-            let (tail_x, tail_y) = snake_bits.remove(0);
-            updates.push((tail_x, tail_y, false));
-        } else {
-            food_pos = (
-                rand::random::<usize>() % config.width as usize,
-                rand::random::<usize>() % config.height as usize,
+            // start over from the initial state, then put back whatever the
+            // message was covering
+            if stage > 0 {
+                updates.extend(state.next_level(levels[0].clone()));
+                stage = 0;
+            }
+            next_level_at = config.foods_per_level;
+            updates.extend(state.reset());
+            log::info!("Game restarted");
+            if let Some(recorder) = &mut recorder {
+                recorder.restart();
+            }
+            for (x, y) in message_cells.drain(..) {
+                updates.push((x, y, state.cell((x, y))));
+            }
+            countdown = config.countdown_secs;
+        }
+
+        // put back what the last digit covered, the next one or the first
+        // move is drawn over it
+        for (x, y) in countdown_cells.drain(..) {
+            updates.push((x, y, state.cell((x, y))));
+        }
+        let counting = countdown > 0;
+        if counting {
+            let (x, y) = countdown_at(state.snakes[0].head(), field);
+            countdown_cells = font::text_cells(&countdown.to_string(), x, y);
+            countdown_cells.retain(|&(x, y)| x < field.0 && y < field.1);
+            for (x, y) in countdown_cells.iter().copied() {
+                overlay.push((x, y, &text));
+            }
+            countdown -= 1;
+        }
+
+        let mut moving = !counting;
+        let mut replayed = None;
+        if let Some(player) = playback.as_mut().filter(|_| moving) {
+            replayed = player.next_tick().map(<[_]>::to_vec);
+            if replayed.is_none() {
+                if !replay_over {
+                    println!("End of the replay, press {:?} to quit", config.keys.quit);
+                    replay_over = true;
+                }
+                moving = false;
+            }
+        }
+        // a replay has every move already
+        let steered = moving && playback.is_none();
+
+        if autopilot && flags.manual.load(Ordering::SeqCst) {
+            log::info!("Autopilot off, back to manual control");
+            autopilot = false;
+        }
+        // turns pressed during the countdown wait in the steering queue
+        if (autopilot || config.autoplay) && steered {
+            game::lock(&steering[0]).push(autopilot::ai_next_dir(&state));
+        }
+        let cursor = *flags.cursor.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(cursor) = cursor.filter(|_| steered) {
+            let (x, y) = state.snakes[0].head();
+            let head = config.screen_pos(x + origin.0, y + origin.1);
+            let [w, h] = config.icon_spacing;
+            if let Some(dir) = input::toward(head, cursor, (w / 2.0, h / 2.0)) {
+                game::lock(&steering[0]).push(dir);
+            }
+        }
+        if let Some(ai) = opponent.filter(|_| steered) {
+            if reaction_clock.is_multiple_of(config.opponent_reaction_ticks) {
+                if let Some(dir) = state.autopilot(ai, config.opponent_skill) {
+                    game::lock(&steering[ai]).push(dir);
+                }
+            }
+            reaction_clock += 1;
+        }
+
+        for (steering, &dir) in steering.iter().zip(replayed.iter().flatten()) {
+            game::lock(steering).reset(dir);
+        }
+        if moving {
+            updates.extend(state.step());
+            log::debug!(
+                "Tick: heads at {:?}, {} cells to redraw",
+                state.snakes.iter().map(|s| s.head()).collect::<Vec<_>>(),
+                updates.len()
             );
-            updates.push((food_pos.0, food_pos.1, true));
+            if let Some(recorder) = &mut recorder {
+                recorder.tick(steering.iter().map(|s| game::lock(s).heading()))?;
+            }
         }
 
-        for (x, y, val) in updates.iter().copied() {
-            let img = if val { &red } else { &black };
-            img.save(Path::new(&desktop).join(format!("ds_p{}-{}.bmp", y, x)))?;
+        let mut level_pause = false;
+        if moving && !levels.is_empty() && !state.game_over && state.foods_eaten >= next_level_at {
+            stage += 1;
+            if stage == levels.len() && config.after_last_level == AfterLastLevel::Win {
+                state.won = true;
+                state.game_over = true;
+            } else {
+                log::info!("Level {}", stage + 1);
+                updates.extend(state.next_level(levels[stage % levels.len()].clone()));
+                next_level_at = state.foods_eaten + config.foods_per_level;
+                level_pause = true;
+            }
         }
 
-        updates.clear();
+        // picks up food eaten this very tick, and the reset on restart
+        let new_tick = config.tick(&state, stage);
+        if new_tick != tick_ms {
+            tick_ms = new_tick;
+            log::debug!("Speed: {} ms per move", tick_ms);
+        }
+        // the move just made is followed by a tick of waiting, shorter if
+        // boosting when it starts. The game still counts a full tick, so a
+        // replay plays out the same without the boosts. A countdown digit
+        // stays up for a second.
+        let wait_ms = if counting {
+            1000
+        } else if flags.boosting.load(Ordering::SeqCst) {
+            config.boost_ms.unwrap_or(tick_ms / 2).clamp(1, tick_ms)
+        } else {
+            tick_ms
+        };
+        if moving {
+            updates.extend(state.elapse(tick_ms));
+            tracker.tick(wait_ms, state.snakes[0].length());
+            if let Some(unlocked) = &mut unlocked {
+                unlocked.check(&achievements::GameStats {
+                    longest: tracker.current().longest,
+                    survived_ms: tracker.current().survived_ms,
+                    score: state.score(),
+                    bonuses_eaten: state.bonuses_eaten,
+                    won: state.won,
+                    walls,
+                })?;
+            }
+        }
+
+        if state.won {
+            println!("You win! Score: {}", state.score());
+        } else if state.game_over && snake_count > 1 {
+            let name = |i| match opponent {
+                Some(ai) if ai == i => "The computer".to_string(),
+                _ => format!("Player {}", i + 1),
+            };
+            let scores: Vec<_> = state.snakes.iter().map(|s| s.score.to_string()).collect();
+            println!("Game over! Scores: {}", scores.join(" to "));
+            let standing: Vec<_> = (0..snake_count)
+                .filter(|&i| state.snakes[i].lives > 0)
+                .collect();
+            match standing[..] {
+                [i] => println!("{} wins", name(i)),
+                _ => println!("Draw"),
+            }
+        } else if state.game_over {
+            println!("Game over! Score: {}", state.score());
+        }
+
+        if state.game_over && state.won {
+            // every snake cell flashes, then the frame puts them back
+            let snakes: Vec<_> = state
+                .cells()
+                .into_iter()
+                .filter(|&(_, _, c)| {
+                    matches!(
+                        c,
+                        Cell::Head(_) | Cell::Snake(_) | Cell::Ghost(_) | Cell::Confused(_)
+                    )
+                })
+                .collect();
+            for n in 0..2 * WIN_FLASHES {
+                let img = if n % 2 == 0 { &victory } else { &black };
+                let _drawing = shutdown::hold();
+                let frame = snakes.iter().map(|&(x, y, _)| (x, y, img));
+                refresh_desktop(&draw_cells(&desktop, origin, frame)?);
+                std::thread::sleep(std::time::Duration::from_millis(FLASH_MS));
+            }
+            updates.extend(snakes);
+        }
+
+        if state.game_over {
+            println!("Press {:?} to restart", config.keys.restart);
+            save_if_record(record_path, state.score(), high_score)?;
+            save_stats(stats_path.as_deref(), &mut tracker, &state, seed)?;
+            if let Some(recorder) = &mut recorder {
+                recorder.save()?;
+            }
+            flags.restart.store(false, Ordering::SeqCst);
+            let message = if state.won { WIN_TEXT } else { GAME_OVER_TEXT };
+            message_cells = font::centered(message, field.0, field.1).unwrap_or_default();
+            for (x, y) in message_cells.iter().copied() {
+                overlay.push((x, y, &text));
+            }
+        }
+
+        if config.gradient {
+            let bands = gradient_bands(&state);
+            for (&(x, y), band) in &bands {
+                let redrawn = updates.iter().any(|&(ux, uy, _)| (ux, uy) == (x, y));
+                if !redrawn && shown_bands.get(&(x, y)) != Some(band) {
+                    updates.push((x, y, state.cell((x, y))));
+                }
+            }
+            shown_bands = bands;
+        }
 
-        // refresh desktop
-        // yeah, doesn't work well
-        //refresh_destop();
+        let drawing = shutdown::hold();
+        let frame_start = Instant::now();
+        let frame = updates.drain(..).map(|(x, y, cell)| {
+            let img = match cell {
+                Cell::Empty => &black,
+                Cell::Head(i) => &heads[i],
+                Cell::Snake(i) => match shown_bands.get(&(x, y)) {
+                    Some(&band) => &body_bands[i][band],
+                    None => &bodies[i],
+                },
+                // see-through while it can cross itself
+                Cell::Ghost(i) => &dim_bodies[i],
+                Cell::GhostFood => &ghost,
+                Cell::Confused(_) | Cell::ConfusionFood => &confused,
+                Cell::SlowFood => &slow,
+                Cell::ShrinkFood => &shrink,
+                Cell::Portal => &portal,
+                Cell::Food(kind) => &foods[kind],
+                Cell::FadingFood(kind) => &fading_foods[kind],
+                Cell::Dead => &dead,
+                Cell::Bonus => &bonus,
+                Cell::Poison => &green,
+                Cell::Wall => &wall,
+            };
+            (x, y, img)
+        });
+        let mut changed = draw_cells(&desktop, origin, frame.chain(overlay))?;
+
+        changed.extend(scoreboard.draw(&desktop, state.score(), state.score() > high_score)?);
 
-        // wait 1 second
+        if let Some(row) = timer_row {
+            let total = config.survival_secs * 1000;
+            let lit = (width as u64 * state.time_left_ms).div_ceil(total) as usize;
+            changed.extend(draw_bar(
+                &desktop,
+                |x| (x, row),
+                lit,
+                &mut timer_shown,
+                &yellow,
+                &black,
+            )?);
+        }
+        if lives_shown {
+            changed.extend(draw_bar(
+                &desktop,
+                |i| (lives_right - 1 - i, 0),
+                state.snakes[0].lives as usize,
+                &mut lives_drawn,
+                &heads[0],
+                &black,
+            )?);
+        }
+
+        let combo = state.snakes[0].combo;
+        if combo_shown && combo != combo_drawn {
+            let icon = (combo as usize)
+                .checked_sub(2)
+                .and_then(|i| combo_icons.get(i))
+                .unwrap_or(&black);
+            let path = cell_path(&desktop, width - 1, 0);
+            if icon.draw(&path)? {
+                changed.push(path);
+            }
+            combo_drawn = combo;
+        }
+
+        refresh_desktop(&changed);
+        log::trace!(
+            "Drew {} icons in {:?}",
+            changed.len(),
+            frame_start.elapsed()
+        );
+        tracker.frame(frame_start.elapsed());
+        drop(drawing);
+        if args.mirror_console {
+            mirror::print(&state, field);
+        }
+
+        if state.crashed() {
+            let lives: Vec<_> = state.snakes.iter().map(|s| s.lives.to_string()).collect();
+            log::info!("Crash! Lives left: {}", lives.join(" to "));
+            let wrecks: Vec<_> = state
+                .cells()
+                .into_iter()
+                .filter(|&(_, _, c)| c == Cell::Dead)
+                .collect();
+            // ends on the wrecks, which is what the game state has drawn
+            for n in 0..2 * RESPAWN_FLASHES {
+                let img = if n % 2 == 0 { &black } else { &dead };
+                let _drawing = shutdown::hold();
+                let frame = wrecks.iter().map(|&(x, y, _)| (x, y, img));
+                refresh_desktop(&draw_cells(&desktop, origin, frame)?);
+                std::thread::sleep(std::time::Duration::from_millis(FLASH_MS));
+            }
+            updates.extend(state.respawn());
+        }
+
+        // wait for the next tick
         // can't really speed that part up
-        std::thread::sleep(std::time::Duration::from_millis(1200));
+        std::thread::sleep(std::time::Duration::from_millis(wait_ms).div_f64(speed));
+        if level_pause {
+            std::thread::sleep(std::time::Duration::from_millis(LEVEL_PAUSE_MS));
+        }
+    }
+}
+
+impl Config {
+    /// Takes the settings an older config has under their old names: the
+    /// colors from before `[colors]`, and `wrap_edges` from before
+    /// `edge_behavior`.
+    fn upgrade(&mut self) -> anyhow::Result<()> {
+        self.colors.adopt(&mut self.old_keys)?;
+        if let Some(wrap) = self.old_keys.remove("wrap_edges") {
+            let wrap = wrap
+                .as_bool()
+                .context("wrap_edges must be true or false, or better use edge_behavior")?;
+            self.edge_behavior = if wrap {
+                EdgeBehavior::Wrap
+            } else {
+                EdgeBehavior::Wall
+            };
+        }
+        Ok(())
+    }
+
+    /// Catches settings that can't work before anything touches the desktop.
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(self.width > 0, "width must be at least 1");
+        anyhow::ensure!(self.height > 0, "height must be at least 1");
+        // a tick of 0 would spin, rewriting the icons as fast as it can
+        anyhow::ensure!(self.tick_ms > 0, "tick_ms must be at least 1");
+        anyhow::ensure!(self.boost_ms != Some(0), "boost_ms must be at least 1");
+        if !self.allow_large {
+            anyhow::ensure!(
+                self.width <= MAX_SIZE && self.height <= MAX_SIZE,
+                "width and height are limited to {} (that's already {} icons), \
+                 set allow_large = true to go beyond",
+                MAX_SIZE,
+                MAX_SIZE * MAX_SIZE
+            );
+            anyhow::ensure!(
+                self.offset <= self.width * self.height,
+                "offset adds more icons than the grid itself ({}), \
+                 set allow_large = true if that's intended",
+                self.width * self.height
+            );
+        }
+        anyhow::ensure!(
+            self.score_rows < self.height,
+            "score_rows must leave at least one row for the snake"
+        );
+        anyhow::ensure!(
+            !(self.score_row && self.score_rows > 0),
+            "score_row and score_rows are mutually exclusive"
+        );
+        anyhow::ensure!(
+            !self.score_row || self.height > 1,
+            "score_row needs a height of at least 2"
+        );
+        anyhow::ensure!(self.food_count > 0, "food_count must be at least 1");
+        anyhow::ensure!(
+            self.pixel_size.is_power_of_two() && (16..=1024).contains(&self.pixel_size),
+            "pixel_size must be a power of two between 16 and 1024"
+        );
+        anyhow::ensure!(self.lives > 0, "lives must be at least 1");
+        anyhow::ensure!(
+            self.opponent_reaction_ticks > 0,
+            "opponent_reaction_ticks must be at least 1"
+        );
+        anyhow::ensure!(self.start_length > 0, "start_length must be at least 1");
+        anyhow::ensure!(self.turn_buffer > 0, "turn_buffer must be at least 1");
+        anyhow::ensure!((1..=2).contains(&self.players), "players must be 1 or 2");
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.poison_chance),
+            "poison_chance must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.ghost_chance),
+            "ghost_chance must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.confusion_chance),
+            "confusion_chance must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.slow_chance),
+            "slow_chance must be between 0 and 1"
+        );
+        anyhow::ensure!(self.slow_factor >= 1.0, "slow_factor must be at least 1");
+        anyhow::ensure!(
+            (0.0..=1.0).contains(&self.shrink_chance),
+            "shrink_chance must be between 0 and 1"
+        );
+        anyhow::ensure!(
+            !(self.maze && self.level.is_some()),
+            "maze and level are mutually exclusive"
+        );
+        anyhow::ensure!(
+            self.levels_dir.is_none() || !(self.maze || self.level.is_some()),
+            "levels_dir can't be combined with maze or level"
+        );
+        anyhow::ensure!(
+            self.mode != Mode::Survival || self.survival_secs > 0,
+            "survival_secs must be at least 1"
+        );
+        anyhow::ensure!(
+            self.foods_per_level > 0,
+            "foods_per_level must be at least 1"
+        );
+
+        self.colors.pixels()?;
+        for (i, kind) in self.food_kinds.iter().enumerate() {
+            kind.color
+                .pixel()
+                .with_context(|| format!("Invalid color of food kind {}", i + 1))?;
+        }
+        anyhow::ensure!(
+            self.food_kinds.is_empty() || self.food_kinds.iter().any(|kind| kind.weight > 0),
+            "at least one food kind needs a weight above 0"
+        );
+
+        let sprites = [
+            ("head_sprite", &self.head_sprite),
+            ("snake_sprite", &self.snake_sprite),
+            ("food_sprite", &self.food_sprite),
+            ("background_sprite", &self.background_sprite),
+        ];
+        for (name, sprite) in sprites {
+            if let Some(path) = sprite {
+                // only reads the header, the whole picture is loaded later
+                image::image_dimensions(path)
+                    .with_context(|| format!("Invalid {} {}", name, path.display()))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the start of `level` to `start_x` and `start_y` if they're
+    /// set, then makes sure the first snake has room there.
+    fn place_start(&self, level: &mut Level, field: (usize, usize)) -> anyhow::Result<()> {
+        let (x, y) = level.start;
+        level.start = (self.start_x.unwrap_or(x), self.start_y.unwrap_or(y));
+        let (x, y) = level.start;
+        anyhow::ensure!(
+            x < field.0 && y < field.1,
+            "start ({}, {}) is outside the {}x{} playfield",
+            x,
+            y,
+            field.0,
+            field.1
+        );
+
+        let body = game::body_from(level.start, self.start_dir, self.start_length, field);
+        anyhow::ensure!(
+            body.len() == self.start_length,
+            "a snake of {} cells heading {:?} from ({}, {}) runs off the playfield",
+            self.start_length,
+            self.start_dir,
+            x,
+            y
+        );
+        if let Some(&(x, y)) = body.iter().find(|c| level.walls.contains(c)) {
+            anyhow::bail!("the starting snake runs into a wall at ({}, {})", x, y);
+        }
+        Ok(())
+    }
+
+    /// Sizes the grid to the screen for `auto_size`, keeping the settings
+    /// as they are if that fails.
+    fn auto_size(&mut self) {
+        #[cfg(windows)]
+        let grid = layout::grid();
+        #[cfg(not(windows))]
+        let grid: anyhow::Result<(u32, u32, [f64; 2])> =
+            Err(anyhow::anyhow!("only supported on Windows"));
+        match grid {
+            Ok((across, down, spacing)) => {
+                self.width = across.max(1);
+                // the offset icons come first, in rows of the same width
+                self.height = down.saturating_sub(self.offset.div_ceil(self.width)).max(1);
+                self.icon_spacing = spacing;
+                log::info!(
+                    "Sized the grid to the screen: {} by {} icons",
+                    self.width,
+                    self.height
+                );
+            }
+            Err(e) => log::warn!(
+                "can't size the grid to the screen, using width {} and height {}: {:#}",
+                self.width,
+                self.height,
+                e
+            ),
+        }
+    }
+
+    /// Roughly where the middle of icon `(x, y)` of the grid is on screen.
+    /// The desktop lays the icons out in rows of `width`, after the `offset`
+    /// ones.
+    fn screen_pos(&self, x: usize, y: usize) -> (f64, f64) {
+        let width = self.width as usize;
+        let index = self.offset as usize + y * width + x;
+        let (col, row) = ((index % width) as f64, (index / width) as f64);
+        let [w, h] = self.icon_spacing;
+        let [left, top] = self.icon_origin;
+        (left + (col + 0.5) * w, top + (row + 0.5) * h)
+    }
+
+    /// Whether leaving the playfield horizontally, then vertically, comes
+    /// back in on the other side.
+    fn wraps(&self) -> (bool, bool) {
+        let wrap = self.edge_behavior == EdgeBehavior::Wrap;
+        (wrap && self.wrap_x, wrap && self.wrap_y)
+    }
+
+    /// Adds the random `obstacles` to a level once its start is placed,
+    /// leaving the starting snake and a few cells ahead of it clear.
+    fn scatter_obstacles(
+        &self,
+        level: &mut Level,
+        field: (usize, usize),
+        rng: &mut StdRng,
+    ) -> anyhow::Result<()> {
+        if self.obstacles == 0 {
+            return Ok(());
+        }
+        let mut keep = game::body_from(level.start, self.start_dir, self.start_length, field);
+        keep.extend(game::body_from(
+            level.start,
+            self.start_dir.opposite(),
+            4,
+            field,
+        ));
+        level::scatter(level, self.obstacles, &keep, field, rng)
+    }
+
+    /// Milliseconds between two moves at this point of the game, `stage`
+    /// levels in, slow motion included.
+    fn tick(&self, state: &GameState, stage: usize) -> u64 {
+        let progress = match self.speed_up {
+            SpeedUp::Food => state.foods_eaten,
+            SpeedUp::Score => state.score(),
+        };
+        // a tick_ms set below the floor is the user's call, keep it
+        let floor = self.speed_min_ms.max(SAFE_TICK_MS).min(self.tick_ms);
+        let ms = self
+            .tick_ms
+            .saturating_sub(self.speed_step_ms.saturating_mul(progress as u64))
+            .saturating_sub(self.level_step_ms.saturating_mul(stage as u64))
+            .max(floor);
+        if state.slowed() {
+            (ms as f64 * self.slow_factor).round() as u64
+        } else {
+            ms
+        }
+    }
+
+    /// The kinds of food, a single one unless `food_kinds` lists some.
+    fn foods(&self) -> Vec<FoodKind> {
+        if self.food_kinds.is_empty() {
+            return vec![FoodKind {
+                growth: self.growth_per_food,
+                score: 1,
+                weight: 1,
+            }];
+        }
+        self.food_kinds
+            .iter()
+            .map(|kind| FoodKind {
+                growth: kind.growth.unwrap_or(self.growth_per_food),
+                score: kind.score,
+                weight: kind.weight,
+            })
+            .collect()
+    }
+
+    fn rules(&self) -> Rules {
+        Rules {
+            food_count: self.food_count,
+            foods: self.foods(),
+            wrap: self.wraps(),
+            solid_walls: self.wall_collision == WallCollision::Solid,
+            collision: match (self.invincible, self.pass_through) {
+                (false, _) => Collision::Crash,
+                (true, false) => Collision::Stop,
+                (true, true) => Collision::Pass,
+            },
+            bonus_interval: self.bonus_interval,
+            bonus_ticks: self.bonus_ticks,
+            bonus_score: self.bonus_score,
+            poison_chance: self.poison_chance,
+            poison_penalty: self.poison_penalty,
+            ghost_chance: self.ghost_chance,
+            ghost_ticks: self.ghost_ticks,
+            confusion_chance: self.confusion_chance,
+            confusion_ticks: self.confusion_ticks,
+            slow_chance: self.slow_chance,
+            slow_ticks: self.slow_ticks,
+            shrink_chance: self.shrink_chance,
+            shrink_segments: self.shrink_segments,
+            mode: self.mode,
+            survival_ms: self.survival_secs * 1000,
+            obstacle_ms: self.obstacle_secs * 1000,
+            obstacle_interval: self.obstacle_interval,
+            max_obstacles: self.max_obstacles,
+            lives: self.lives,
+            start_dir: self.start_dir,
+            start_length: self.start_length,
+            shrink_interval: self.shrink_interval,
+            shrink_min_area: self.shrink_min_area,
+            food_ttl: self.food_ttl_ticks,
+            combo_ticks: self.combo_ticks,
+            opponent: self.opponent,
+        }
     }
 }
 
-fn clear_old_files(desktop: &PathBuf) -> anyhow::Result<()> {
+/// The gradient band of every body cell of the living snakes. A segment's
+/// band is its share of the way from the head to the tail, so whether the
+/// snake grows or not, a move only changes the band of the few cells
+/// around each edge between two of them.
+fn gradient_bands(state: &GameState) -> HashMap<(usize, usize), usize> {
+    let mut bands = HashMap::new();
+    for snake in state.snakes.iter().filter(|s| s.alive) {
+        let len = snake.length().saturating_sub(1).max(1);
+        for (k, cell) in snake.body().enumerate() {
+            // a ghost crossing itself shows its nearer segment
+            bands.entry(cell).or_insert(k * GRADIENT_BANDS / len);
+        }
+    }
+    bands
+}
+
+/// Top-left corner of a countdown digit next to the snake's `head`: above
+/// it if there's room, below otherwise, and kept inside the `field`.
+fn countdown_at(head: (usize, usize), field: (usize, usize)) -> (usize, usize) {
+    let (x, y) = head;
+    let x = x.min(field.0.saturating_sub(font::GLYPH_WIDTH));
+    let y = if y > font::GLYPH_HEIGHT {
+        y - font::GLYPH_HEIGHT - 1
+    } else {
+        (y + 2).min(field.1.saturating_sub(font::GLYPH_HEIGHT))
+    };
+    (x, y)
+}
+
+/// Saves playfield cells, offset by `origin` on the grid, and returns the
+/// paths written.
+fn draw_cells<'a>(
+    desktop: &Path,
+    origin: (usize, usize),
+    cells: impl IntoIterator<Item = (usize, usize, &'a Icon)>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for (x, y, img) in cells {
+        let path = cell_path(desktop, x + origin.0, y + origin.1);
+        if img.draw(&path)? {
+            changed.push(path);
+        }
+    }
+    Ok(changed)
+}
+
+/// Redraws the cells of a bar that changed since it was `shown` cells
+/// long, so it's `lit` cells long now. `at` gives the grid position of each
+/// cell of the bar.
+fn draw_bar(
+    desktop: &Path,
+    at: impl Fn(usize) -> (usize, usize),
+    lit: usize,
+    shown: &mut usize,
+    on: &Icon,
+    off: &Icon,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut changed = Vec::new();
+    for i in lit.min(*shown)..lit.max(*shown) {
+        let (x, y) = at(i);
+        let path = cell_path(desktop, x, y);
+        if (if i < lit { on } else { off }).draw(&path)? {
+            changed.push(path);
+        }
+    }
+    *shown = lit;
+    Ok(changed)
+}
+
+/// Writes `icon` to every one of `paths`, at once on every core when the
+/// rayon feature is on. Only meant for the initial grid, a frame only
+/// touches a handful of cells.
+fn fill(icon: &Icon, paths: &[PathBuf]) -> anyhow::Result<()> {
+    #[cfg(feature = "rayon")]
+    {
+        use rayon::prelude::*;
+        paths.par_iter().try_for_each(|path| icon.save(path))
+    }
+    #[cfg(not(feature = "rayon"))]
+    {
+        paths.iter().try_for_each(|path| icon.save(path))
+    }
+}
+
+/// Three quarters as bright.
+fn trail(color: bmp::Pixel) -> bmp::Pixel {
+    let darker = |c: u8| (c as u16 * 3 / 4) as u8;
+    bmp::Pixel::new(darker(color.r), darker(color.g), darker(color.b))
+}
+
+/// `color` darkened for gradient band `band`, a quarter of the way to
+/// black per band with four of them.
+fn shade(color: bmp::Pixel, band: usize) -> bmp::Pixel {
+    let lit = (GRADIENT_BANDS - band) as u16;
+    let darker = |c: u8| (c as u16 * lit / GRADIENT_BANDS as u16) as u8;
+    bmp::Pixel::new(darker(color.r), darker(color.g), darker(color.b))
+}
+
+/// Half as bright.
+fn dim(color: bmp::Pixel) -> bmp::Pixel {
+    bmp::Pixel::new(color.r / 2, color.g / 2, color.b / 2)
+}
+
+// set once from the config, every icon file and name follows it
+static ICON_FORMAT: OnceLock<IconFormat> = OnceLock::new();
+
+fn icon_format() -> IconFormat {
+    ICON_FORMAT.get().copied().unwrap_or_default()
+}
+
+// writes that failed in a row during the game, see `Icon::draw`
+static FAILED_WRITES: AtomicUsize = AtomicUsize::new(0);
+const MAX_FAILED_WRITES: usize = 100;
+
+/// An image encoded once, so drawing it on any number of cells is just
+/// writing the same bytes over and over.
+#[derive(Clone)]
+pub struct Icon {
+    bytes: Vec<u8>,
+}
+
+impl Icon {
+    /// Encodes `img` in the configured `image_format`.
+    pub fn new(img: &Image) -> Icon {
+        let mut bytes = Vec::new();
+        match icon_format() {
+            IconFormat::Bmp => img.to_writer(&mut bytes),
+            IconFormat::Png => {
+                let rgb = image::RgbImage::from_fn(img.get_width(), img.get_height(), |x, y| {
+                    let p = img.get_pixel(x, y);
+                    image::Rgb([p.r, p.g, p.b])
+                });
+                rgb.write_to(
+                    &mut std::io::Cursor::new(&mut bytes),
+                    image::ImageFormat::Png,
+                )
+                .map_err(std::io::Error::other)
+            }
+        }
+        .expect("writing to a Vec can't fail");
+        Icon { bytes }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        write_cached(&self.bytes, path.as_ref())
+    }
+
+    /// Saves the icon during the game, where a failed write only skips
+    /// the cell: Explorer holding the file open is over by the next frame
+    /// or so. The game only stops after `MAX_FAILED_WRITES` failures in a
+    /// row. Returns whether the file was written.
+    pub fn draw(&self, path: &Path) -> anyhow::Result<bool> {
+        match self.save(path) {
+            Ok(()) => {
+                FAILED_WRITES.store(0, Ordering::SeqCst);
+                Ok(true)
+            }
+            Err(error) => {
+                let failed = FAILED_WRITES.fetch_add(1, Ordering::SeqCst) + 1;
+                anyhow::ensure!(
+                    failed < MAX_FAILED_WRITES,
+                    "{:#}, giving up after {} failed writes in a row",
+                    error,
+                    failed
+                );
+                log::error!("{:#}", error);
+                Ok(false)
+            }
+        }
+    }
+}
+
+/// Writes an already encoded icon to `path`. Filling 1024 cells with a
+/// 256x256 icon took 330 ms with `Image::save` against 125 ms for this in
+/// a release build, and 5.5 s against 120 ms in a debug one.
+pub fn write_cached(bytes: &[u8], path: &Path) -> anyhow::Result<()> {
+    std::fs::write(path, bytes).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The picture at `path` squeezed into a `size`x`size` icon.
+fn load_sprite(path: &Path, size: u32) -> anyhow::Result<Icon> {
+    let picture = image::open(path)
+        .with_context(|| format!("Failed to load {}", path.display()))?
+        .resize_exact(size, size, image::imageops::FilterType::Triangle)
+        .to_rgb8();
+    let mut img = Image::new(size, size);
+    for (x, y) in img.coordinates() {
+        let [r, g, b] = picture.get_pixel(x, y).0;
+        img.set_pixel(x, y, bmp::Pixel::new(r, g, b));
+    }
+    Ok(Icon::new(&img))
+}
+
+/// A `size`x`size` icon filled with `color`.
+pub fn solid_image(color: bmp::Pixel, size: u32) -> Icon {
+    let mut img = Image::new(size, size);
+    for (x, y) in img.coordinates() {
+        img.set_pixel(x, y, color);
+    }
+    Icon::new(&img)
+}
+
+pub fn cell_path(desktop: &Path, x: usize, y: usize) -> PathBuf {
+    desktop.join(format!("ds_p{}-{}.{}", y, x, icon_format().extension()))
+}
+
+/// Adds the game `tracker` followed to the stats at `path`, if any, and
+/// starts tracking the next one. `seed` is the one this session started
+/// from.
+fn save_stats(
+    path: Option<&Path>,
+    tracker: &mut Tracker,
+    state: &GameState,
+    seed: u64,
+) -> anyhow::Result<()> {
+    match (
+        path,
+        tracker.finish(state.score(), state.foods_eaten, state.won),
+    ) {
+        (Some(path), Some(run)) => stats::save(path, stats::Run { seed, ..run }),
+        _ => Ok(()),
+    }
+}
+
+/// Saves `score` to `path` if it beats the `high_score`. Without a path,
+/// nothing counts as a record.
+fn save_if_record(path: Option<&Path>, score: u32, high_score: u32) -> anyhow::Result<()> {
+    let Some(path) = path else {
+        return Ok(());
+    };
+    if score > high_score {
+        println!("New high score!");
+        highscore::save_high_score(path, score).context("Failed to save high score")?;
+    }
+    Ok(())
+}
+
+/// Deletes our icons from `desktop`, returning their paths.
+fn clear_old_files(desktop: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
     for entry in std::fs::read_dir(desktop)? {
         let entry = entry?;
         let path = entry.path();
@@ -178,10 +2001,16 @@ fn clear_old_files(desktop: &PathBuf) -> anyhow::Result<()> {
                 .starts_with("ds_")
         {
             std::fs::remove_file(&path)?;
+            removed.push(path);
         }
     }
 
-    Ok(())
+    Ok(removed)
+}
+
+fn write_default_config(path: &Path) -> anyhow::Result<()> {
+    std::fs::write(path, DEFAULT_CONFIG)
+        .with_context(|| format!("Failed to write {}", path.display()))
 }
 
 fn get_desktop_dir() -> anyhow::Result<PathBuf> {
@@ -192,3 +2021,32 @@ fn get_desktop_dir() -> anyhow::Result<PathBuf> {
         .join("snake");
     Ok(desktop)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `text` read the way the game reads its config file.
+    fn load(name: &str, text: &str) -> Config {
+        let path = std::env::temp_dir().join(format!("desktop-snake-{}.toml", name));
+        std::fs::write(&path, text).unwrap();
+        let mut config = Config::from_config_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        config.upgrade().unwrap();
+        config
+    }
+
+    #[test]
+    fn wrap_edges_becomes_edge_behavior() {
+        let config = load(
+            "wall",
+            "width = 8\nheight = 8\noffset = 0\nwrap_edges = false\n",
+        );
+        assert!(config.edge_behavior == EdgeBehavior::Wall);
+        let config = load(
+            "wrap",
+            "width = 8\nheight = 8\noffset = 0\nwrap_edges = true\n",
+        );
+        assert!(config.edge_behavior == EdgeBehavior::Wrap);
+    }
+}
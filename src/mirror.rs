@@ -0,0 +1,37 @@
+use crate::game::{Cell, GameState};
+use std::fmt::Write;
+
+/// Two characters per cell, so the board looks about square in a terminal.
+fn glyph(cell: Cell) -> &'static str {
+    match cell {
+        Cell::Empty => "  ",
+        Cell::Head(_) => "██",
+        Cell::Snake(_) | Cell::Ghost(_) | Cell::Confused(_) => "▓▓",
+        Cell::Dead => "░░",
+        Cell::Food(_) | Cell::FadingFood(_) => "()",
+        Cell::Bonus => "$$",
+        Cell::Poison => "><",
+        Cell::GhostFood | Cell::ConfusionFood | Cell::SlowFood | Cell::ShrinkFood => "??",
+        Cell::Portal => "[]",
+        Cell::Wall => "▒▒",
+    }
+}
+
+/// Prints the `field` sized board of `state` over the previous one, to see
+/// what the game thinks is there whatever the desktop shows.
+pub fn print(state: &GameState, field: (usize, usize)) {
+    // clear the screen and go back to the top
+    let mut out = String::from("\x1b[2J\x1b[H");
+    let border = "─".repeat(2 * field.0);
+    let _ = writeln!(out, "┌{}┐", border);
+    for y in 0..field.1 {
+        out.push('│');
+        for x in 0..field.0 {
+            out.push_str(glyph(state.cell((x, y))));
+        }
+        out.push_str("│\n");
+    }
+    let _ = writeln!(out, "└{}┘", border);
+    let _ = write!(out, "Score: {}", state.score());
+    println!("{}", out);
+}
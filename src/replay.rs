@@ -0,0 +1,222 @@
+use crate::game::SnakeDir;
+use crate::Config;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bumped on every change to `Replay`, so an old file is refused instead
+/// of being misread.
+pub const VERSION: u32 = 1;
+
+/// Where recordings go, next to the config file by default.
+pub const DIR: &str = "replays";
+
+// ticks between two saves while recording
+const SAVE_TICKS: usize = 100;
+
+/// Everything needed to play a run again: the seed and settings it started
+/// from, then what every snake did on every tick.
+#[derive(Serialize, Deserialize)]
+pub struct Replay {
+    pub version: u32,
+    pub seed: u64,
+    pub config: Config,
+    /// One letter per snake per tick, `U`, `D`, `L` or `R`, for the way it
+    /// went.
+    pub moves: String,
+    /// Ticks played before each restart.
+    pub restarts: Vec<u64>,
+}
+
+// settings that only change how the replay is watched
+const LOCAL_SETTINGS: [&str; 6] = [
+    "seed",
+    "record",
+    "output_dir",
+    "keys",
+    "boost_ms",
+    "countdown_secs",
+];
+
+/// Reads a replay saved by `Recorder`, refusing other versions of the
+/// format.
+pub fn load(path: &Path) -> anyhow::Result<Replay> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read replay {}", path.display()))?;
+    let value: serde_json::Value = match serde_json::from_str(&text) {
+        Ok(value) => value,
+        // saves replace the whole file at once, so this is a bad copy
+        Err(e) if e.is_eof() => anyhow::bail!("Replay {} is cut short", path.display()),
+        Err(e) => return Err(e).with_context(|| format!("Invalid replay {}", path.display())),
+    };
+    let version = value.get("version").and_then(|v| v.as_u64());
+    anyhow::ensure!(
+        version == Some(VERSION as u64),
+        "{} is a version {} replay, only version {} can be played",
+        path.display(),
+        version.map_or("unknown".to_string(), |v| v.to_string()),
+        VERSION
+    );
+    serde_json::from_value(value).with_context(|| format!("Invalid replay {}", path.display()))
+}
+
+impl Replay {
+    /// The settings to play the replay with, which are the recorded ones
+    /// since anything else would play out differently. Only the output
+    /// folder and the keys come from `current`, and the settings it
+    /// disagrees on are reported.
+    pub fn settings(&self, current: &Config) -> anyhow::Result<Config> {
+        let recorded = serde_json::to_value(&self.config)?;
+        let current_value = serde_json::to_value(current)?;
+        let differ: Vec<_> = recorded
+            .as_object()
+            .into_iter()
+            .flatten()
+            .filter(|(key, value)| {
+                !LOCAL_SETTINGS.contains(&key.as_str()) && current_value.get(key) != Some(value)
+            })
+            .map(|(key, _)| key.as_str())
+            .collect();
+        if !differ.is_empty() {
+            log::warn!(
+                "the replay was recorded with different {}, playing it with those",
+                differ.join(", ")
+            );
+        }
+
+        Ok(Config {
+            output_dir: current.output_dir.clone(),
+            keys: current.keys,
+            record: false,
+            ..self.config.clone()
+        })
+    }
+}
+
+/// Hands out the recorded moves tick by tick.
+pub struct Player {
+    moves: Vec<SnakeDir>,
+    snakes: usize,
+    ticks: u64,
+    restarts: VecDeque<u64>,
+}
+
+impl Player {
+    pub fn new(replay: &Replay) -> anyhow::Result<Player> {
+        let snakes = replay.config.players + replay.config.opponent as usize;
+        let mut moves = replay
+            .moves
+            .chars()
+            .map(|c| from_letter(c).with_context(|| format!("Invalid move {:?} in replay", c)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        if moves.len() % snakes != 0 {
+            log::warn!("the replay's last tick is incomplete, it stops before it");
+            moves.truncate(moves.len() - moves.len() % snakes);
+        }
+        Ok(Player {
+            moves,
+            snakes,
+            ticks: 0,
+            restarts: replay.restarts.iter().copied().collect(),
+        })
+    }
+
+    /// Which way each snake goes on the next tick, `None` once the
+    /// recording is over.
+    pub fn next_tick(&mut self) -> Option<&[SnakeDir]> {
+        let start = self.ticks as usize * self.snakes;
+        let moves = self.moves.get(start..start + self.snakes)?;
+        self.ticks += 1;
+        Some(moves)
+    }
+
+    /// Whether the game was restarted at this point of the recording.
+    pub fn restart_due(&mut self) -> bool {
+        let due = self.restarts.front() == Some(&self.ticks);
+        if due {
+            self.restarts.pop_front();
+        }
+        due
+    }
+}
+
+/// Builds a replay as the game goes, saving it from time to time.
+pub struct Recorder {
+    path: PathBuf,
+    replay: Replay,
+    ticks: u64,
+    unsaved: usize,
+}
+
+impl Recorder {
+    /// Starts a recording in a new file of `dir`, named after the time.
+    pub fn new(dir: &Path, seed: u64, config: Config) -> anyhow::Result<Recorder> {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create {}", dir.display()))?;
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs());
+        let path = dir.join(format!("replay-{}.json", now));
+        log::info!("Recording to {}", path.display());
+        Ok(Recorder {
+            path,
+            replay: Replay {
+                version: VERSION,
+                seed,
+                config,
+                moves: String::new(),
+                restarts: Vec::new(),
+            },
+            ticks: 0,
+            unsaved: 0,
+        })
+    }
+
+    /// Adds a tick where the snakes went `headings`. Saves every once in a
+    /// while, so a crash of the program loses little.
+    pub fn tick(&mut self, headings: impl IntoIterator<Item = SnakeDir>) -> anyhow::Result<()> {
+        self.replay.moves.extend(headings.into_iter().map(letter));
+        self.ticks += 1;
+        self.unsaved += 1;
+        if self.unsaved >= SAVE_TICKS {
+            self.save()?;
+        }
+        Ok(())
+    }
+
+    pub fn restart(&mut self) {
+        self.replay.restarts.push(self.ticks);
+    }
+
+    /// Writes everything so far, replacing the previous save.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        let json = serde_json::to_string(&self.replay)?;
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, json)
+            .and_then(|_| std::fs::rename(&tmp, &self.path))
+            .with_context(|| format!("Failed to save replay {}", self.path.display()))?;
+        self.unsaved = 0;
+        Ok(())
+    }
+}
+
+fn from_letter(letter: char) -> Option<SnakeDir> {
+    match letter {
+        'U' => Some(SnakeDir::Up),
+        'D' => Some(SnakeDir::Down),
+        'L' => Some(SnakeDir::Left),
+        'R' => Some(SnakeDir::Right),
+        _ => None,
+    }
+}
+
+fn letter(dir: SnakeDir) -> char {
+    match dir {
+        SnakeDir::Up => 'U',
+        SnakeDir::Down => 'D',
+        SnakeDir::Left => 'L',
+        SnakeDir::Right => 'R',
+    }
+}
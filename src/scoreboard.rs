@@ -0,0 +1,162 @@
+use crate::color::Pixels;
+use crate::{cell_path, font, solid_image, Icon};
+use bmp::{Image, Pixel};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// Score display above the playfield. Either a strip of `rows` rows where
+/// the number is drawn with the 3x5 font, one cell per font pixel, or a
+/// single row with one digit icon per cell.
+pub struct Scoreboard {
+    width: usize,
+    rows: Option<usize>,
+    blank: Icon,
+    on: [Icon; 2],
+    digits: [Vec<Icon>; 2],
+    record: bool,
+    lit: HashSet<(usize, usize)>,
+    shown: Vec<Option<u32>>,
+}
+
+impl Scoreboard {
+    /// `pixel_size` is the size of the icon images, `blank` what the cells
+    /// without a digit show, the same as an empty cell of the playfield.
+    /// The digits are `colors.digit`, or `colors.record` once the score
+    /// beats the saved high score.
+    pub fn new(
+        width: usize,
+        rows: Option<usize>,
+        pixel_size: u32,
+        blank: Icon,
+        colors: &Pixels,
+    ) -> Scoreboard {
+        let digits = |color| {
+            (0..10)
+                .map(|d| digit_image(d, color, colors.background, pixel_size))
+                .collect()
+        };
+        Scoreboard {
+            width,
+            rows,
+            blank,
+            on: [
+                solid_image(colors.digit, pixel_size),
+                solid_image(colors.record, pixel_size),
+            ],
+            digits: [digits(colors.digit), digits(colors.record)],
+            record: false,
+            lit: HashSet::new(),
+            shown: vec![None; width],
+        }
+    }
+
+    /// Only rewrites the cells that changed since the previous call, or all
+    /// of them when `record` flips since the color changes. Returns the
+    /// files that were written.
+    pub fn draw(
+        &mut self,
+        desktop: &Path,
+        score: u32,
+        record: bool,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let repaint = record != self.record;
+        self.record = record;
+        match self.rows {
+            Some(rows) => self.draw_digits(desktop, score, rows, repaint),
+            None => self.draw_digit_icons(desktop, score, repaint),
+        }
+    }
+
+    fn draw_digits(
+        &mut self,
+        desktop: &Path,
+        score: u32,
+        rows: usize,
+        repaint: bool,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let width = self.width;
+        let mut digits = score.to_string();
+        // keep the least significant digits if the number doesn't fit
+        while digits.len() > 1 && font::text_width(&digits) > width {
+            digits.remove(0);
+        }
+
+        let top = rows.saturating_sub(font::GLYPH_HEIGHT) / 2;
+        let cells: HashSet<_> = font::text_cells(&digits, 0, top)
+            .into_iter()
+            .filter(|&(x, y)| x < width && y < rows)
+            .collect();
+
+        let mut written = Vec::new();
+        let on = &self.on[self.record as usize];
+        for &(x, y) in cells.iter() {
+            if repaint || !self.lit.contains(&(x, y)) {
+                let path = cell_path(desktop, x, y);
+                if on.draw(&path)? {
+                    written.push(path);
+                }
+            }
+        }
+        for &(x, y) in self.lit.difference(&cells) {
+            let path = cell_path(desktop, x, y);
+            if self.blank.draw(&path)? {
+                written.push(path);
+            }
+        }
+
+        self.lit = cells;
+        Ok(written)
+    }
+
+    fn draw_digit_icons(
+        &mut self,
+        desktop: &Path,
+        score: u32,
+        repaint: bool,
+    ) -> anyhow::Result<Vec<PathBuf>> {
+        let mut wanted: Vec<_> = score
+            .to_string()
+            .chars()
+            .filter_map(|c| c.to_digit(10))
+            .collect();
+        // keep the least significant digits if the number doesn't fit
+        if wanted.len() > self.width {
+            wanted.drain(..wanted.len() - self.width);
+        }
+
+        let mut written = Vec::new();
+        let digits = &self.digits[self.record as usize];
+        for (x, cell) in self.shown.iter_mut().enumerate() {
+            let digit = wanted.get(x).copied();
+            if *cell != digit || (repaint && digit.is_some()) {
+                let img = digit.map_or(&self.blank, |d| &digits[d as usize]);
+                let path = cell_path(desktop, x, 0);
+                if img.draw(&path)? {
+                    written.push(path);
+                }
+                *cell = digit;
+            }
+        }
+
+        Ok(written)
+    }
+}
+
+/// 3x5 glyph of `digit` in `color`, scaled up on a `background`-colored
+/// `size` icon.
+fn digit_image(digit: u32, color: Pixel, background: Pixel, size: u32) -> Icon {
+    let lit: HashSet<_> = font::text_cells(&digit.to_string(), 0, 0)
+        .into_iter()
+        .collect();
+    // 3 columns plus a margin of 2 on each side, 5 rows plus 1 around
+    let block = size / 7;
+
+    let mut img = Image::new(size, size);
+    for (x, y) in img.coordinates() {
+        let gx = (x / block) as i64 - 2;
+        let gy = (y / block) as i64 - 1;
+        let on = gx >= 0 && gy >= 0 && lit.contains(&(gx as usize, gy as usize));
+        img.set_pixel(x, y, if on { color } else { background });
+    }
+    Icon::new(&img)
+}
@@ -0,0 +1,63 @@
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard, OnceLock};
+
+// where the icons live, for the handler
+static DESKTOP: OnceLock<PathBuf> = OnceLock::new();
+
+// held while writing icons, so the cleanup can't race with a frame
+static DRAWING: Mutex<()> = Mutex::new(());
+
+/// Keeps the cleanup from running until the returned guard is dropped.
+pub fn hold() -> MutexGuard<'static, ()> {
+    DRAWING.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Removes every icon we put on `desktop` and tells Explorer about it,
+/// then moves the user's icons back where they were.
+pub fn cleanup(desktop: &Path) {
+    match crate::clear_old_files(desktop) {
+        Ok(removed) => crate::refresh_desktop(&removed),
+        Err(error) => log::error!("Failed to clean up the desktop: {:?}", error),
+    }
+    #[cfg(windows)]
+    if let Err(error) = crate::layout::restore() {
+        log::error!("{:#}", error);
+    }
+}
+
+fn cleanup_and_exit() -> ! {
+    // never released, we're on our way out
+    let _drawing = hold();
+    if let Some(desktop) = DESKTOP.get() {
+        cleanup(desktop);
+    }
+    std::process::exit(0);
+}
+
+/// Cleans up `desktop` on Ctrl+C or when the console window is closed.
+#[cfg(windows)]
+pub fn install(desktop: &Path) -> anyhow::Result<()> {
+    use anyhow::Context;
+    use windows::Win32::Foundation::BOOL;
+    use windows::Win32::System::Console::SetConsoleCtrlHandler;
+
+    // Windows kills the process as soon as this returns for a window close,
+    // so the cleanup has to happen in here and not on another thread
+    unsafe extern "system" fn handler(_event: u32) -> BOOL {
+        cleanup_and_exit()
+    }
+
+    let _ = DESKTOP.set(desktop.to_path_buf());
+    unsafe { SetConsoleCtrlHandler(Some(handler), true) }
+        .ok()
+        .context("Failed to install the console handler")
+}
+
+/// Cleans up `desktop` on Ctrl+C, SIGTERM or SIGHUP.
+#[cfg(not(windows))]
+pub fn install(desktop: &Path) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let _ = DESKTOP.set(desktop.to_path_buf());
+    ctrlc::set_handler(|| cleanup_and_exit()).context("Failed to install the Ctrl+C handler")
+}
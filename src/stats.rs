@@ -0,0 +1,126 @@
+use anyhow::Context;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// How one game went, a line of the stats file.
+#[derive(Serialize, Deserialize, Default)]
+pub struct Run {
+    pub score: u32,
+    pub foods: u32,
+    /// Ended with a win, like a full board.
+    #[serde(default)]
+    pub won: bool,
+    /// Most segments the first snake had at once.
+    pub longest: usize,
+    /// Game time played, counting every tick's wait but not pauses.
+    pub survived_ms: u64,
+    pub ticks: u64,
+    /// Time spent writing icons and telling the desktop, per tick.
+    pub frame_ms: f64,
+    /// What the random number generator started from, to play the same
+    /// boards again with `--seed`.
+    #[serde(default)]
+    pub seed: u64,
+}
+
+/// Where stats go unless the config says otherwise.
+pub fn default_path() -> Option<PathBuf> {
+    ProjectDirs::from("", "", "desktop-snake").map(|dirs| dirs.data_dir().join("stats.jsonl"))
+}
+
+/// Keeps track of the game being played.
+#[derive(Default)]
+pub struct Tracker {
+    run: Run,
+    frame_time: Duration,
+}
+
+impl Tracker {
+    /// Counts a tick that waited `tick_ms`, with the first snake
+    /// `length` long.
+    pub fn tick(&mut self, tick_ms: u64, length: usize) {
+        self.run.ticks += 1;
+        self.run.survived_ms += tick_ms;
+        self.run.longest = self.run.longest.max(length);
+    }
+
+    /// The game so far, score and foods aside.
+    pub fn current(&self) -> &Run {
+        &self.run
+    }
+
+    pub fn frame(&mut self, took: Duration) {
+        self.frame_time += took;
+    }
+
+    /// The game so far, and a fresh tracker for the next one. `None` if
+    /// it never got going.
+    pub fn finish(&mut self, score: u32, foods: u32, won: bool) -> Option<Run> {
+        let mut tracker = std::mem::take(self);
+        if tracker.run.ticks == 0 {
+            return None;
+        }
+        tracker.run.score = score;
+        tracker.run.foods = foods;
+        tracker.run.won = won;
+        tracker.run.frame_ms = tracker.frame_time.as_secs_f64() * 1000.0 / tracker.run.ticks as f64;
+        Some(tracker.run)
+    }
+}
+
+/// Every run saved in `path`. A missing file means none yet; a corrupt one
+/// is reported and left out, and `save` starts it over.
+fn load(path: &Path) -> Option<Vec<Run>> {
+    let Ok(text) = std::fs::read_to_string(path) else {
+        return Some(Vec::new());
+    };
+    match text.lines().map(serde_json::from_str).collect() {
+        Ok(runs) => Some(runs),
+        Err(_) => {
+            log::warn!("starting over from corrupt stats file {}", path.display());
+            None
+        }
+    }
+}
+
+/// Adds `run` to the stats in `path` and prints how it compares.
+pub fn save(path: &Path, run: Run) -> anyhow::Result<()> {
+    let old = load(path);
+    let line = serde_json::to_string(&run)?;
+    let write = || -> std::io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(old.is_some())
+            .write(true)
+            .truncate(old.is_none())
+            .open(path)?;
+        writeln!(file, "{}", line)
+    };
+    write().with_context(|| format!("Failed to save stats to {}", path.display()))?;
+
+    log::info!(
+        "This game: {} foods, {} long at most, {:.1} s, {:.1} ms per frame",
+        run.foods,
+        run.longest,
+        run.survived_ms as f64 / 1000.0,
+        run.frame_ms
+    );
+    let mut runs = old.unwrap_or_default();
+    runs.push(run);
+    let most = |f: fn(&Run) -> u64| runs.iter().map(f).max().unwrap_or(0);
+    log::info!(
+        "All time: {} games, {} won, {} foods, {} long at most, {:.1} s at most",
+        runs.len(),
+        runs.iter().filter(|r| r.won).count(),
+        runs.iter().map(|r| r.foods as u64).sum::<u64>(),
+        most(|r| r.longest as u64),
+        most(|r| r.survived_ms) as f64 / 1000.0
+    );
+    Ok(())
+}